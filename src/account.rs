@@ -0,0 +1,250 @@
+use chain::{Block, Error};
+use codec::Encoder;
+use hash::{BlockHasher, Hash, Sha256};
+use merkle;
+use transaction::Transaction;
+
+use std::collections::BTreeMap;
+
+/// The balance and transaction count of an address.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct Account {
+    pub balance: u64,
+    /// Number of transactions the address has sent, which is the nonce
+    /// its next transaction must carry.
+    pub nonce: u64,
+}
+
+/// Accounts changed by a run of transactions on top of an `AccountState`,
+/// kept apart from the state itself.
+#[derive(Debug,Clone,Default)]
+pub struct AccountChanges {
+    changed: BTreeMap<String, Account>,
+}
+
+/// The accounts a block changed, as they were before it.
+#[derive(Debug,Clone,Default)]
+pub struct AccountUndo {
+    previous: Vec<(String, Option<Account>)>,
+}
+
+// the SHA-256 hash of an account's encoding, a leaf of the state tree
+fn account_leaf(address: &str, account: &Account) -> Hash {
+    let mut encoder = Encoder::new();
+    encoder.bytes(address.as_bytes()).u64(account.balance).u64(account.nonce);
+    Sha256.digest(&encoder.finish())
+}
+
+/// Balances and nonces of every address that has held coins, for chains
+/// that keep account state instead of unspent outputs.
+///
+/// A transaction pays out of the balance of its `sender`, whose nonce it
+/// must carry, and credits its outputs to their recipients. Only the
+/// genesis block and the coinbase leading a block may credit coins without
+/// a sender. The state is committed to by its root, the root of a Merkle
+/// tree over the accounts ordered by address.
+#[derive(Debug,Clone,Default)]
+pub struct AccountState {
+    accounts: BTreeMap<String, Account>,
+}
+
+impl AccountState {
+    /// Creates a state without any accounts.
+    pub fn new() -> AccountState {
+        AccountState::default()
+    }
+
+    /// Number of accounts.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Whether there are no accounts.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Looks up the account of `address`, if it has ever held coins.
+    pub fn get(&self, address: &str) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    /// Coins held in all accounts.
+    pub fn supply(&self) -> u64 {
+        self.accounts.values().fold(0, |total, account| total.saturating_add(account.balance))
+    }
+
+    /// Root of the Merkle tree over the accounts, built with `hasher`.
+    pub fn root(&self, hasher: &dyn BlockHasher) -> Hash {
+        let leaves: Vec<Hash> = self.accounts.iter().map(|(address, account)| account_leaf(address, account)).collect();
+        merkle::merkle_root(hasher, &leaves)
+    }
+
+    /// Root of the state after applying `block`, or the reason the block
+    /// cannot be applied.
+    pub fn root_after(&self, hasher: &dyn BlockHasher, block: &Block) -> Result<Hash, Error> {
+        let mut accounts: BTreeMap<&str, Account> = self.accounts.iter().map(|(address, account)| (address.as_str(), *account)).collect();
+        let changes = self.transition(block)?;
+        for (address, account) in &changes.changed {
+            accounts.insert(address, *account);
+        }
+
+        let leaves: Vec<Hash> = accounts.iter().map(|(address, account)| account_leaf(address, account)).collect();
+        Ok(merkle::merkle_root(hasher, &leaves))
+    }
+
+    /// Checks that every transaction in `block` can be applied in turn.
+    pub fn check(&self, block: &Block) -> Result<(), Error> {
+        self.transition(block).map(|_| ())
+    }
+
+    /// Checks that `tx`, if added to the block at height `index`, could be
+    /// applied to the state.
+    pub fn check_transaction(&self, index: u32, tx: &Transaction) -> Result<(), Error> {
+        self.apply_pending(&mut AccountChanges::default(), index, tx)
+    }
+
+    /// Applies a block that passed `check`, returning what is needed to
+    /// roll it back.
+    pub fn apply(&mut self, block: &Block) -> AccountUndo {
+        let changes = self.transition(block).expect("block was checked before it was applied");
+        let mut undo = AccountUndo::default();
+        for (address, account) in changes.changed {
+            let previous = self.accounts.insert(address.clone(), account);
+            undo.previous.push((address, previous));
+        }
+        undo
+    }
+
+    /// Rolls back the last block applied, with the undo data its `apply`
+    /// returned.
+    pub fn undo(&mut self, undo: AccountUndo) {
+        for (address, previous) in undo.previous {
+            match previous {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+        }
+    }
+
+    // the accounts `block` changes, with their new values
+    fn transition(&self, block: &Block) -> Result<AccountChanges, Error> {
+        let mut changes = AccountChanges::default();
+        for tx in block.transactions() {
+            self.apply_pending(&mut changes, block.index(), tx)?;
+        }
+        Ok(changes)
+    }
+
+    /// Checks that `tx`, if added to the block at height `index` after the
+    /// transactions that made `changes`, carries its sender's next nonce and
+    /// is covered by the sender's balance, and adds its own changes if so.
+    pub fn apply_pending(&self, changes: &mut AccountChanges, index: u32, tx: &Transaction) -> Result<(), Error> {
+        let txid = tx.txid();
+        if !tx.inputs.is_empty() {
+            let reason = "spends unspent outputs, which an account chain does not keep".to_string();
+            return Err(Error::InvalidTransaction { index, txid, reason });
+        }
+
+        // collect the new values first, so a failing transaction changes nothing
+        let mut updates: BTreeMap<&str, Account> = BTreeMap::new();
+        let lookup = |updates: &BTreeMap<&str, Account>, address: &str| {
+            updates
+                .get(address)
+                .or_else(|| changes.changed.get(address))
+                .or_else(|| self.accounts.get(address))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        if let Some(ref sender) = tx.sender {
+            let mut account = lookup(&updates, sender);
+            if tx.nonce != account.nonce {
+                return Err(Error::NonceMismatch { index, txid, expected: account.nonce, found: tx.nonce });
+            }
+            let spent = tx.total_spent().unwrap_or(u64::MAX);
+            if spent > account.balance {
+                return Err(Error::Overspend { index, txid, available: account.balance, spent });
+            }
+            account.balance -= spent;
+            account.nonce += 1;
+            updates.insert(sender, account);
+        }
+
+        for output in &tx.outputs {
+            let mut account = lookup(&updates, &output.recipient);
+            account.balance = account.balance.checked_add(output.amount).ok_or_else(|| Error::InvalidTransaction {
+                index,
+                txid,
+                reason: format!("credits {} more coins than can be counted", output.recipient),
+            })?;
+            updates.insert(&output.recipient, account);
+        }
+
+        for (address, account) in updates {
+            changes.changed.insert(address.to_string(), account);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{Keypair, Scheme};
+    use test_utils::allocate;
+    use transaction::{OutPoint, Output};
+
+    fn pay(keypair: &Keypair, nonce: u64, recipient: &str, amount: u64) -> Transaction {
+        let mut tx = Transaction {
+            sender: Some(keypair.public_key().address()),
+            outputs: vec![Output { recipient: recipient.to_string(), amount }],
+            fee: 1,
+            nonce,
+            ..Transaction::default()
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    #[test]
+    fn apply_and_undo_follow_the_root() {
+        let alice = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&alice.public_key().address(), 10);
+        let mut state = AccountState::new();
+        state.apply(&genesis);
+        let before = state.root(&Sha256);
+
+        let block = Block::new(&genesis, vec![pay(&alice, 0, "bob", 3), pay(&alice, 1, "bob", 2)], 1, 0, &Sha256);
+        let expected = state.root_after(&Sha256, &block).unwrap();
+        let undo = state.apply(&block);
+        assert_eq!(state.root(&Sha256), expected);
+        assert_eq!(state.get("bob"), Some(&Account { balance: 5, nonce: 0 }));
+        assert_eq!(state.get(&alice.public_key().address()), Some(&Account { balance: 3, nonce: 2 }));
+
+        state.undo(undo);
+        assert_eq!(state.root(&Sha256), before);
+        assert!(state.get("bob").is_none());
+    }
+
+    #[test]
+    fn check_enforces_nonces_and_balances() {
+        let alice = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&alice.public_key().address(), 10);
+        let mut state = AccountState::new();
+        state.apply(&genesis);
+        let block = |transactions| Block::new(&genesis, transactions, 1, 0, &Sha256);
+
+        let replayed = pay(&alice, 0, "bob", 1);
+        let result = state.check(&block(vec![replayed.clone(), replayed]));
+        assert!(matches!(result, Err(Error::NonceMismatch { index: 1, expected: 1, found: 0, .. })));
+
+        let result = state.check(&block(vec![pay(&alice, 0, "bob", 5), pay(&alice, 1, "bob", 5)]));
+        assert!(matches!(result, Err(Error::Overspend { available: 4, spent: 6, .. })));
+
+        let mut spend = Transaction { inputs: vec![OutPoint { txid: Hash::zero(), index: 0 }], ..Transaction::default() };
+        spend.sign(&alice);
+        assert!(matches!(state.check_transaction(1, &spend), Err(Error::InvalidTransaction { .. })));
+    }
+}
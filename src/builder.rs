@@ -0,0 +1,182 @@
+use chain::{Block, Blockchain};
+use mempool::{Mempool, PoolEntry};
+use transaction::Transaction;
+
+/// Default limit on the encoded size of a block's transactions, in bytes.
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Assembles the next block for a miner out of the transactions waiting in
+/// a `Mempool`.
+///
+/// Pooled transactions are picked paying the most per byte first, as long
+/// as they fit the size limit and apply on top of the main chain and the
+/// transactions picked before them. A coinbase in front creates the block
+/// reward and collects their fees for the builder's recipient. The block
+/// template that results commits to its transactions and state and only
+/// needs a `Miner` to find its nonce.
+#[derive(Debug,Clone)]
+pub struct BlockBuilder {
+    recipient: String,
+    max_size: usize,
+}
+
+impl BlockBuilder {
+    /// Creates a builder whose blocks pay their coinbase to `recipient`.
+    pub fn new(recipient: &str) -> BlockBuilder {
+        BlockBuilder {
+            recipient: recipient.to_string(),
+            max_size: DEFAULT_MAX_BLOCK_SIZE,
+        }
+    }
+
+    /// Limits the encoded size of a block's transactions, coinbase
+    /// included, to `max_size` bytes.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Picks the pooled transactions for the block that follows the main
+    /// chain's tip of `blockchain`, in the order they apply in.
+    pub fn select(&self, blockchain: &Blockchain, mempool: &Mempool) -> Vec<Transaction> {
+        let state = blockchain.state();
+        let index = blockchain.len() as u32;
+        let mut changes = state.pending();
+        let mut room = self.max_size.saturating_sub(Transaction::coinbase(index, &self.recipient, 1).size());
+
+        // the pool may not have caught up with the chain yet
+        let mut waiting: Vec<&PoolEntry> = mempool
+            .entries()
+            .into_iter()
+            .filter(|entry| blockchain.get_transaction(&entry.txid).is_none())
+            .collect();
+
+        // a transaction that builds on one not picked yet is tried again on
+        // the next pass, until a pass picks nothing
+        let mut selected = Vec::new();
+        loop {
+            let picked = selected.len();
+            waiting.retain(|entry| {
+                if entry.size > room {
+                    return false;
+                }
+                match state.apply_pending(&mut changes, index, &entry.tx) {
+                    Ok(()) => {
+                        room -= entry.size;
+                        selected.push(entry.tx.clone());
+                        false
+                    }
+                    Err(_) => true,
+                }
+            });
+            if selected.len() == picked {
+                break;
+            }
+        }
+        selected
+    }
+
+    /// Builds the unmined block that follows the main chain's tip of
+    /// `blockchain`, carrying the selected transactions behind a coinbase
+    /// that pays out the block reward and their fees. A block with neither
+    /// has no coinbase.
+    pub fn build(&self, blockchain: &Blockchain, mempool: &Mempool) -> Block {
+        let index = blockchain.len() as u32;
+        let selected = self.select(blockchain, mempool);
+        let fees = selected.iter().fold(0u64, |total, tx| total.saturating_add(tx.fee));
+        let reward = blockchain.params().issuance.reward(index);
+
+        let mut transactions = Vec::with_capacity(selected.len() + 1);
+        let payout = reward.saturating_add(fees);
+        if payout > 0 {
+            transactions.push(Transaction::coinbase(index, &self.recipient, payout));
+        }
+        transactions.extend(selected);
+
+        debug!(index, transactions = transactions.len(), reward, fees, "built block template");
+        blockchain.candidate(transactions)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::Hash;
+    use keys::{Keypair, Scheme};
+    use miner::Miner;
+    use state::StateModel;
+    use transaction::{OutPoint, Output};
+    use test_utils::funded;
+
+    fn spend(keypair: &Keypair, input: OutPoint, amount: u64, fee: u64) -> Transaction {
+        let mut tx = Transaction {
+            inputs: vec![input],
+            outputs: vec![Output { recipient: keypair.public_key().address(), amount: amount - fee }],
+            fee,
+            ..Transaction::default()
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    #[test]
+    fn templates_collect_fees_and_are_mined() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let mut blockchain = funded(&keypair, &[10, 10], StateModel::Utxo);
+        let genesis = blockchain.genesis().transactions()[0].txid();
+
+        // the child pays more per byte than its parent, but has to follow it
+        let parent = spend(&keypair, OutPoint { txid: genesis, index: 0 }, 10, 1);
+        let child = spend(&keypair, OutPoint { txid: parent.txid(), index: 0 }, 9, 4);
+        let other = spend(&keypair, OutPoint { txid: genesis, index: 1 }, 10, 2);
+        let mut mempool = Mempool::new();
+        for tx in [parent.clone(), child.clone(), other.clone()] {
+            mempool.submit(&blockchain, tx).unwrap();
+        }
+
+        let template = BlockBuilder::new("miner").build(&blockchain, &mempool);
+        let txids: Vec<Hash> = template.transactions().iter().map(Transaction::txid).collect();
+        assert_eq!(txids, vec![Transaction::coinbase(1, "miner", 7).txid(), other.txid(), parent.txid(), child.txid()]);
+
+        let (block, _) = Miner::new(2).mine(&template, blockchain.algorithm(), &blockchain.watch_tip());
+        blockchain.append(block.unwrap()).unwrap();
+        assert_eq!(blockchain.balance("miner"), 7);
+        mempool.sync(&blockchain);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn selection_fits_the_size_limit() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let blockchain = funded(&keypair, &[100], StateModel::Account);
+        let pay = |nonce, fee| {
+            let mut tx = Transaction {
+                sender: Some(keypair.public_key().address()),
+                outputs: vec![Output { recipient: "bob".to_string(), amount: 5 }],
+                fee,
+                nonce,
+                ..Transaction::default()
+            };
+            tx.sign(&keypair);
+            tx
+        };
+        let mut mempool = Mempool::new();
+        for (nonce, fee) in [(0, 1), (1, 3), (2, 2)] {
+            mempool.submit(&blockchain, pay(nonce, fee)).unwrap();
+        }
+
+        // room for the coinbase and two transactions, taken in nonce order
+        let mut builder = BlockBuilder::new("miner");
+        builder.set_max_size(Transaction::coinbase(1, "miner", 1).size() + pay(0, 1).size() * 2);
+        let nonces: Vec<u64> = builder.select(&blockchain, &mempool).iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1]);
+
+        let template = builder.build(&blockchain, &mempool);
+        assert_eq!(template.transactions()[0].outputs[0].amount, 4);
+        assert!(blockchain.state().check(&blockchain.algorithm(), &template).is_ok());
+    }
+}
@@ -1,196 +1,2119 @@
-extern crate crypto;
+use clock::{Clock, SystemClock};
+use codec::{Decoder, Encoder};
+use genesis::GenesisSpec;
+use hash::{Algorithm, BlockHasher, Hash};
+use issuance::Issuance;
+use merkle::{self, MerkleProof};
+use miner::TipWatch;
+use state::{State, StateModel, StateUndo};
+use store::{BlockStore, FileStore};
+use transaction::{OutPoint, Output, Transaction};
+#[cfg(feature = "serde")]
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 
-use self::crypto::digest::Digest;
-use self::crypto::sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
-        InvalidBlock {
-            description("The block was invalid")
+        /// The genesis block is malformed or its hash does not match its contents.
+        InvalidGenesis {
+            display("The genesis block was invalid")
         }
-        InvalidChain {
-            description("The chain was invalid")
+        /// The chain starts from a different genesis block.
+        GenesisMismatch {
+            display("The chain starts from a different genesis block")
+        }
+        /// The block index does not follow its predecessor.
+        IndexMismatch { expected: u32, found: u32 } {
+            display("Expected block index {} but found {}", expected, found)
+        }
+        /// The block does not reference the hash of its predecessor.
+        PrevHashMismatch { index: u32 } {
+            display("Block {} does not reference the previous block hash", index)
+        }
+        /// The stored hash does not match the block contents.
+        HashMismatch { index: u32 } {
+            display("Block {} hash does not match its contents", index)
+        }
+        /// The block is older than the median time of the blocks before it.
+        TimestampRegression { index: u32 } {
+            display("Block {} is older than the median of recent blocks", index)
+        }
+        /// The block is dated too far ahead of the local clock.
+        TimestampTooFar { index: u32 } {
+            display("Block {} is too far in the future", index)
+        }
+        /// The block uses a header version this node cannot hash.
+        UnsupportedVersion { index: u32, version: u32 } {
+            display("Block {} uses unsupported version {}", index, version)
+        }
+        /// The block's state root does not match the state after applying it.
+        StateRootMismatch { index: u32 } {
+            display("Block {} does not commit to the state that results from it", index)
+        }
+        /// The block declares a different difficulty than the chain requires.
+        DifficultyMismatch { index: u32, expected: u32, found: u32 } {
+            display("Block {} declares difficulty {} but {} is required", index, found, expected)
+        }
+        /// The block hash does not satisfy the required proof of work.
+        InsufficientWork { index: u32 } {
+            display("Block {} does not meet the required proof of work", index)
+        }
+        /// The block's transactions do not match the Merkle root in its header.
+        MerkleMismatch { index: u32 } {
+            display("Block {} transactions do not match its Merkle root", index)
+        }
+        /// A transaction in the block breaks the transaction rules.
+        InvalidTransaction { index: u32, txid: Hash, reason: String } {
+            display("Block {} transaction {} is invalid: {}", index, txid, reason)
+        }
+        /// The block carries the same transaction more than once, or one
+        /// that an earlier main chain block already carries.
+        DuplicateTransaction { index: u32, txid: Hash } {
+            display("Block {} repeats transaction {}", index, txid)
+        }
+        /// The block's coinbase pays out more than the block reward and fees.
+        CoinbaseTooLarge { index: u32, allowed: u64, found: u64 } {
+            display("Block {} coinbase pays out {} coins but may only create and collect {}", index, found, allowed)
+        }
+        /// A transaction in the block is missing the signature it needs, or
+        /// carries one that does not verify.
+        InvalidSignature { index: u32, txid: Hash } {
+            display("Block {} transaction {} is not properly signed", index, txid)
+        }
+        /// A transaction spends an output that does not exist or was already spent.
+        MissingOutput { index: u32, txid: Hash, input: OutPoint } {
+            display("Block {} transaction {} spends {}:{}, which is missing or already spent", index, txid, input.txid, input.index)
+        }
+        /// A transaction spends an output paid to a different key than the one that signed it.
+        ForeignOutput { index: u32, txid: Hash, input: OutPoint } {
+            display("Block {} transaction {} spends {}:{} without the key it was paid to", index, txid, input.txid, input.index)
+        }
+        /// A transaction does not carry the next nonce of its sender's account.
+        NonceMismatch { index: u32, txid: Hash, expected: u64, found: u64 } {
+            display("Block {} transaction {} carries nonce {} but {} is next", index, txid, found, expected)
+        }
+        /// A transaction pays out more coins than its inputs hold.
+        Overspend { index: u32, txid: Hash, available: u64, spent: u64 } {
+            display("Block {} transaction {} spends {} coins but its inputs hold {}", index, txid, spent, available)
+        }
+        /// A transaction pays out fewer coins than its inputs hold, which would destroy the rest.
+        Underspend { index: u32, txid: Hash, available: u64, spent: u64 } {
+            display("Block {} transaction {} spends {} coins but its inputs hold {}", index, txid, spent, available)
+        }
+        /// The transaction is already in the pool or on the main chain.
+        KnownTransaction(txid: Hash) {
+            display("Transaction {} is already known", txid)
+        }
+        /// The transaction pool is full of transactions paying more per byte.
+        PoolFull(txid: Hash) {
+            display("Transaction {} pays too little to stay in the full transaction pool", txid)
+        }
+        /// The block's parent is not in the block tree.
+        UnknownParent { index: u32 } {
+            display("Block {} does not follow any known block", index)
+        }
+        /// A chain segment grows from a block that is not in the block tree.
+        UnknownAncestor(hash: Hash) {
+            display("Ancestor block {} is not known", hash)
+        }
+        /// The block is already in the block tree.
+        KnownBlock { index: u32 } {
+            display("Block {} is already known", index)
+        }
+        /// The replacement chain carries less cumulative work than the local one.
+        LessWork { local: u128, remote: u128 } {
+            display("Chain with work {} does not exceed local work {}", remote, local)
+        }
+        /// The replacement chain has as much work as the local one but loses the tie-break.
+        LostTieBreak {
+            display("Chain has equal work but its tip hash does not win the tie-break")
+        }
+        /// The chain failed validation, see the source for the reason.
+        InvalidChain(err: Box<Error>) {
+            display("The chain was invalid: {}", err)
+            source(&**err)
+        }
+        /// A block store holds a damaged record that is not the result of an interrupted write.
+        CorruptStore { offset: u64 } {
+            display("Block store is corrupt at offset {}", offset)
+        }
+        /// The text is not a well-formed address.
+        InvalidAddress(address: String) {
+            display("'{}' is not a valid address", address)
+        }
+        /// No key in the wallet belongs to the address.
+        UnknownAddress(address: String) {
+            display("The wallet holds no key for {}", address)
+        }
+        /// The address does not hold enough coins for the payment.
+        InsufficientFunds { needed: u64, available: u64 } {
+            display("Payment needs {} coins but only {} are available", needed, available)
+        }
+        /// The keystore could not be decrypted with the passphrase.
+        InvalidPassphrase {
+            display("Wrong passphrase or damaged keystore")
+        }
+        /// Serialized data could not be decoded.
+        Decode(reason: String) {
+            display("Could not decode: {}", reason)
+        }
+        /// A line of a genesis spec could not be parsed.
+        InvalidSpec(line: usize, reason: String) {
+            display("Invalid genesis spec on line {}: {}", line, reason)
+        }
+        /// A file or database could not be read or written.
+        Io(err: io::Error) {
+            from()
+            display("I/O error: {}", err)
+            source(err)
+        }
+    }
+}
+
+/// Header version written into new blocks. Blocks with any other version
+/// are rejected as unsupported.
+pub const BLOCK_VERSION: u32 = 1;
+
+// canonical header encoding, the bytes the block hash covers
+//
+// Transactions are committed to through the Merkle root, so the header
+// stays the same size however many a block carries, and the state after the
+// block is committed to next to the hash of the block before it.
+fn encode_header(block: &Block, encoder: &mut Encoder) {
+    encoder
+        .u32(block.version)
+        .u32(block.index)
+        .u64(block.timestamp)
+        .hash(&block.prev_hash)
+        .hash(&block.state_root)
+        .hash(&block.merkle_root)
+        .u32(block.difficulty)
+        .u64(block.nonce);
+}
+
+// reads the fields written by `encode_header` after the version
+fn decode_header(decoder: &mut Decoder) -> Result<Block, Error> {
+    Ok(Block {
+        version: BLOCK_VERSION,
+        index: decoder.u32()?,
+        timestamp: decoder.u64()?,
+        prev_hash: decoder.hash()?,
+        state_root: decoder.hash()?,
+        merkle_root: decoder.hash()?,
+        difficulty: decoder.u32()?,
+        nonce: decoder.u64()?,
+        hash: Hash::zero(),
+        transactions: Vec::new(),
+    })
+}
+
+// root of the Merkle tree over the ids of `transactions`
+fn transactions_root(hasher: &dyn BlockHasher, transactions: &[Transaction]) -> Hash {
+    let txids: Vec<Hash> = transactions.iter().map(Transaction::txid).collect();
+    merkle::merkle_root(hasher, &txids)
+}
+
+fn calc_hash(block: &Block, hasher: &dyn BlockHasher) -> Result<Hash, Error> {
+    let mut encoder = Encoder::new();
+    match block.version {
+        BLOCK_VERSION => encode_header(block, &mut encoder),
+        version => return Err(Error::UnsupportedVersion { index: block.index, version }),
+    }
+
+    Ok(hasher.digest(&encoder.finish()))
+}
+
+/// Consensus rules a chain is configured with.
+#[derive(Debug,Clone,Copy,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Params {
+    /// Hash function used for every block.
+    pub algorithm: Algorithm,
+    /// Intended time between blocks, in milliseconds.
+    pub block_time: u64,
+    /// Number of blocks between difficulty adjustments.
+    pub retarget_window: u32,
+    /// Whether coins are kept in unspent outputs or account balances.
+    pub state_model: StateModel,
+    /// New coins each block's coinbase may create.
+    pub issuance: Issuance,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            algorithm: Algorithm::Sha256,
+            block_time: 10_000,
+            retarget_window: 10,
+            state_model: StateModel::Utxo,
+            issuance: Issuance::default(),
         }
     }
 }
 
-fn calc_hash(index: &u32, timestamp: &u32, prev_hash: &str, payload: &str) -> String {
-    let record = format!("{}{}{}{}",
-        index,
-        timestamp,
-        prev_hash,
-        payload
-    );
+const CHAIN_MAGIC: &[u8; 8] = b"BLKCHAIN";
+
+/// Format written by `Blockchain::to_bytes`, which takes a new version
+/// whenever the encoding changes.
+pub const CHAIN_FORMAT_VERSION: u32 = 1;
+
+/// Highest difficulty a block can declare, as no hash has more leading zeros.
+pub const MAX_DIFFICULTY: u32 = 256;
+
+// number of blocks before a new block needed to check it against the rules
+fn context_len(params: &Params) -> usize {
+    MEDIAN_TIME_SPAN.max(params.retarget_window as usize)
+}
+
+// difficulty required of the block that follows `ancestors`
+//
+// Every `retarget_window` blocks the time taken by the last window is compared
+// with the intended time. Difficulty is counted in leading zero bits, so each
+// step doubles or halves the expected work.
+fn next_difficulty(params: &Params, ancestors: &[Block]) -> u32 {
+    let prev_block = &ancestors[ancestors.len() - 1];
+    let window = params.retarget_window as usize;
+    let height = prev_block.index as usize + 1;
+
+    if window == 0 || height < window || !height.is_multiple_of(window) {
+        return prev_block.difficulty;
+    }
+
+    let first = &ancestors[ancestors.len() - window];
+    let actual = prev_block.timestamp.saturating_sub(first.timestamp);
+    let expected = params.block_time.saturating_mul(window as u64);
 
-    // create a Sha256 object
-    let mut hasher = Sha256::new();
-    hasher.input_str(&record);
-    let hex = hasher.result_str();
+    if actual < expected / 2 {
+        (prev_block.difficulty + 1).min(MAX_DIFFICULTY)
+    } else if actual > expected.saturating_mul(2) {
+        prev_block.difficulty.saturating_sub(1)
+    } else {
+        prev_block.difficulty
+    }
+}
 
-    return hex;
+/// Expected number of hashes needed to mine a block at `difficulty`.
+pub fn block_work(difficulty: u32) -> u128 {
+    1u128.checked_shl(difficulty).unwrap_or(u128::MAX)
 }
 
-fn is_block_valid(prev_block: &Block, new_block: &Block) -> bool {
+/// The rule that made `Blockchain::replace` accept a competing chain.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ForkChoice {
+    /// The competing chain carries more cumulative work.
+    MoreWork,
+    /// Both chains carry equal work and the competing tip has the lower hash.
+    TieBreak,
+}
+
+// decides whether a chain ending in `remote_tip` should replace the one
+// ending in `local_tip`, given the cumulative work of each
+fn choose_fork(local_work: u128, local_tip: &Hash, remote_work: u128, remote_tip: &Hash) -> Result<ForkChoice, Error> {
+    if remote_work > local_work {
+        Ok(ForkChoice::MoreWork)
+    } else if remote_work < local_work {
+        Err(Error::LessWork { local: local_work, remote: remote_work })
+    } else if remote_tip < local_tip {
+        // every node orders equal-work tips the same way, so they all settle on one
+        Ok(ForkChoice::TieBreak)
+    } else {
+        Err(Error::LostTieBreak)
+    }
+}
+
+/// How far ahead of the local clock a block may be dated, in milliseconds.
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60 * 1000;
+
+/// Number of recent blocks whose median timestamp a new block must not precede.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+// median timestamp of the last MEDIAN_TIME_SPAN blocks
+fn median_time_past(ancestors: &[Block]) -> u64 {
+    let start = ancestors.len().saturating_sub(MEDIAN_TIME_SPAN);
+    let mut timestamps: Vec<u64> = ancestors[start..].iter().map(|block| block.timestamp).collect();
+    timestamps.sort();
+    timestamps[timestamps.len() / 2]
+}
+
+// `ancestors` holds the blocks before `new_block`, ending with its parent,
+// going back at least `context_len` blocks or to genesis
+//
+// Only blocks new to the node are dated against the local time `now`. Blocks
+// it accepted before are reloaded with `None`, as the clock may have been set
+// back since.
+fn is_block_valid(
+    params: &Params,
+    ancestors: &[Block],
+    new_block: &Block,
+    now: Option<u64>,
+) -> Result<(), Error> {
+    let prev_block = &ancestors[ancestors.len() - 1];
+
     // check invalid conditions
     if prev_block.index + 1 != new_block.index {
-        return false;
+        return Err(Error::IndexMismatch { expected: prev_block.index + 1, found: new_block.index });
     }
 
     if prev_block.hash != new_block.prev_hash {
-        return false;
+        return Err(Error::PrevHashMismatch { index: new_block.index });
     }
 
-    if calc_hash(
-        &new_block.index,
-        &new_block.timestamp,
-        &new_block.prev_hash,
-        &new_block.payload ) != new_block.hash 
-    {
-        return false;
+    if new_block.timestamp < median_time_past(ancestors) {
+        return Err(Error::TimestampRegression { index: new_block.index });
     }
 
-    // otherwise return true
-    return true;
-}
+    if now.is_some_and(|now| new_block.timestamp > now.saturating_add(MAX_FUTURE_DRIFT)) {
+        return Err(Error::TimestampTooFar { index: new_block.index });
+    }
 
-fn is_chain_valid(current_chain: &Blockchain, new_chain: &Blockchain) -> bool {
-    // compare genesis blocks to ensure same origin
-    if let Some(genesis_block) = current_chain.blocks.first().cloned() {
-        if let Some(new_origin) = new_chain.blocks.first().cloned() {
-            let genesis_block_hash = calc_hash(
-                &genesis_block.index,
-                &genesis_block.timestamp, 
-                &genesis_block.prev_hash, 
-                &genesis_block.payload
-            );
+    let expected = next_difficulty(params, ancestors);
+    if new_block.difficulty != expected {
+        return Err(Error::DifficultyMismatch {
+            index: new_block.index,
+            expected,
+            found: new_block.difficulty,
+        });
+    }
 
-            let new_origin_hash = calc_hash(
-                &new_origin.index, 
-                &new_origin.timestamp, 
-                &new_origin.prev_hash, 
-                &new_origin.payload
-            );
+    if calc_hash(new_block, &params.algorithm)? != new_block.hash {
+        return Err(Error::HashMismatch { index: new_block.index });
+    }
 
-            if genesis_block_hash != new_origin_hash {
-                println!("Genesis block mismatch!");
-                return false;
-            }
+    if !new_block.hash.meets_difficulty(new_block.difficulty) {
+        return Err(Error::InsufficientWork { index: new_block.index });
+    }
 
-            // verify each block in chain is valid
-            let mut prev_block = new_origin;
-            let mut index = 0;
-
-            for new_block in new_chain.blocks.iter() {
-                // skip origin
-                if index > 0 {
-                    if is_block_valid(&prev_block, new_block) {
-                        continue;
-                    } else {
-                        println!("Invalid block detected with index {}!", new_block.index);
-                        return false;
-                    }
-                }
-                index += 1; // increment index to skip first one
-            }
-            
-            return true;
-        } else {
-            println!("Missing new chain origin!");
-            return false;
+    if transactions_root(&params.algorithm, &new_block.transactions) != new_block.merkle_root {
+        return Err(Error::MerkleMismatch { index: new_block.index });
+    }
+
+    let mut txids = HashSet::new();
+    for (position, tx) in new_block.transactions.iter().enumerate() {
+        let txid = tx.txid();
+        let checked = if position == 0 && tx.is_coinbase() { tx.check_coinbase() } else { tx.check() };
+        if let Err(reason) = checked {
+            return Err(Error::InvalidTransaction { index: new_block.index, txid, reason });
+        }
+        if !tx.is_signed() {
+            return Err(Error::InvalidSignature { index: new_block.index, txid });
+        }
+        if !txids.insert(txid) {
+            return Err(Error::DuplicateTransaction { index: new_block.index, txid });
+        }
+    }
+
+    // the coinbase may create the block reward and collect the fees the
+    // block's transactions leave
+    if let Some(coinbase) = new_block.transactions.first().filter(|tx| tx.is_coinbase()) {
+        // carrying the height keeps the coinbase's id apart from every other block's
+        if coinbase.nonce != u64::from(new_block.index) {
+            let reason = format!("coinbase carries height {} instead of {}", coinbase.nonce, new_block.index);
+            return Err(Error::InvalidTransaction { index: new_block.index, txid: coinbase.txid(), reason });
+        }
+
+        let fees = new_block.transactions[1..].iter().fold(0u64, |total, tx| total.saturating_add(tx.fee));
+        let allowed = params.issuance.reward(new_block.index).saturating_add(fees);
+        let found = coinbase.total_spent().unwrap_or(u64::MAX);
+        if found > allowed {
+            return Err(Error::CoinbaseTooLarge { index: new_block.index, allowed, found });
         }
-    } else {
-        println!("Could not find genesis block");
-        return false;
     }
+
+    // otherwise the block is valid
+    Ok(())
 }
 
+fn is_genesis_valid(hasher: &dyn BlockHasher, block: &Block) -> bool {
+    // every coin of the chain starts here, so their total must be countable
+    let allocated = block.transactions
+        .iter()
+        .flat_map(|tx| tx.outputs.iter())
+        .try_fold(0u64, |total, output| total.checked_add(output.amount));
+
+    block.index == 0
+        && block.prev_hash.is_zero()
+        && block.difficulty <= MAX_DIFFICULTY
+        && calc_hash(block, hasher).ok() == Some(block.hash)
+        && transactions_root(hasher, &block.transactions) == block.merkle_root
+        && allocated.is_some()
+}
+
+// only blocks the node can hash are read, as `Block::from_bytes` does
+#[cfg(feature = "serde")]
+fn deserialize_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    match u32::deserialize(deserializer)? {
+        BLOCK_VERSION => Ok(BLOCK_VERSION),
+        version => Err(de::Error::custom(format!("unsupported block version {}", version))),
+    }
+}
+
+/// A single block in the chain.
 #[derive(Debug,Clone)]
-struct Block {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Block {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_version"))]
+    version: u32,
     index: u32,
-    timestamp: u32,
-    hash: String,
-    prev_hash: String,
-    payload: String,
+    timestamp: u64,
+    difficulty: u32,
+    nonce: u64,
+    hash: Hash,
+    prev_hash: Hash,
+    state_root: Hash,
+    merkle_root: Hash,
+    transactions: Vec<Transaction>,
 }
 
 impl Block {
-    pub fn new(prev_block: &Block, payload: &str) -> Block {
-        let index = prev_block.index + 1;
-        let timestamp = prev_block.timestamp + 10;
-        let prev_hash = format!("{}", prev_block.hash);
-    
-        Block {
-            hash: calc_hash(&index, &timestamp, &prev_hash, &payload),
+    /// Creates the first block of a chain, hashed with `hasher`.
+    ///
+    /// The genesis block does not need to meet its own `difficulty`, which
+    /// only sets the starting difficulty for the blocks that follow.
+    pub fn genesis(transactions: Vec<Transaction>, timestamp: u64, difficulty: u32, hasher: &dyn BlockHasher) -> Block {
+        Block::with_hash(hasher, 0, timestamp, difficulty, Hash::zero(), transactions)
+    }
+
+    /// Creates the block that follows `prev_block` and carries `transactions`,
+    /// dated `timestamp` milliseconds since the Unix epoch and hashed with
+    /// `hasher`.
+    ///
+    /// The block is hashed once with a zero nonce and still has to be
+    /// mined before it meets `difficulty`.
+    pub fn new(
+        prev_block: &Block,
+        transactions: Vec<Transaction>,
+        timestamp: u64,
+        difficulty: u32,
+        hasher: &dyn BlockHasher,
+    ) -> Block {
+        Block::with_hash(hasher, prev_block.index + 1, timestamp, difficulty, prev_block.hash, transactions)
+    }
+
+    fn with_hash(
+        hasher: &dyn BlockHasher,
+        index: u32,
+        timestamp: u64,
+        difficulty: u32,
+        prev_hash: Hash,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let mut block = Block {
+            version: BLOCK_VERSION,
             index,
             timestamp,
+            difficulty,
+            nonce: 0,
+            hash: Hash::zero(),
             prev_hash,
-            payload: String::from(payload),
+            state_root: Hash::zero(),
+            merkle_root: transactions_root(hasher, &transactions),
+            transactions,
+        };
+        block.hash = calc_hash(&block, hasher).expect("current block version is always supported");
+        block
+    }
+
+    /// Commits the block to `state_root`, the root of the chain state after
+    /// the block is applied, and rehashes it with `hasher`.
+    pub fn with_state_root(mut self, state_root: Hash, hasher: &dyn BlockHasher) -> Block {
+        self.state_root = state_root;
+        self.hash = calc_hash(&self, hasher).expect("current block version is always supported");
+        self
+    }
+
+    /// Searches for a nonce whose hash meets the block's difficulty.
+    ///
+    /// This runs on the calling thread; use a `Miner` to search on several
+    /// threads and abandon the search when the chain moves on.
+    pub fn mine(&mut self, hasher: &dyn BlockHasher) {
+        let mut nonce = self.nonce;
+        while !self.hash.meets_difficulty(self.difficulty) {
+            nonce = nonce.wrapping_add(1);
+            self.try_nonce(nonce, hasher);
         }
     }
+
+    // rehashes the block with `nonce` and reports whether it meets the difficulty
+    pub(crate) fn try_nonce(&mut self, nonce: u64, hasher: &dyn BlockHasher) -> bool {
+        self.nonce = nonce;
+        self.hash = calc_hash(self, hasher).expect("current block version is always supported");
+        self.hash.meets_difficulty(self.difficulty)
+    }
+
+    /// Version of the header format, which decides how the block is hashed.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Height of the block, starting at 0 for the genesis block.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Time the block was created, in milliseconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Number of leading zero bits the block hash must have.
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    /// Value varied while mining to search for a hash that meets the difficulty.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Hash of the canonical encoding of the block header.
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    /// Hash of the preceding block, all zeroes for the genesis block.
+    pub fn prev_hash(&self) -> &Hash {
+        &self.prev_hash
+    }
+
+    /// Root of the chain state after the block, zero on chains that do not
+    /// commit to one.
+    pub fn state_root(&self) -> &Hash {
+        &self.state_root
+    }
+
+    /// Root of the Merkle tree over the ids of the block's transactions.
+    pub fn merkle_root(&self) -> &Hash {
+        &self.merkle_root
+    }
+
+    /// Data carried by the block: that of its first transaction moving no
+    /// coins, such as the one `Blockchain::add_block` creates, or an empty
+    /// string if every transaction moves coins.
+    pub fn payload(&self) -> &str {
+        self.transactions
+            .iter()
+            .find(|tx| tx.inputs.is_empty() && tx.sender.is_none() && tx.outputs.is_empty() && tx.fee == 0)
+            .map_or("", |tx| tx.data.as_str())
+    }
+
+    /// Transactions carried by the block, in the order they apply.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Builds the proof that the transaction with `txid` is committed to by
+    /// the block's Merkle root, using the chain's hash function `hasher`.
+    pub fn prove_transaction(&self, hasher: &dyn BlockHasher, txid: &Hash) -> Option<MerkleProof> {
+        let txids: Vec<Hash> = self.transactions.iter().map(Transaction::txid).collect();
+        let index = txids.iter().position(|id| id == txid)?;
+        merkle::prove(hasher, &txids, index)
+    }
+
+    /// Encodes the block compactly: the canonical header encoding, the
+    /// transactions and then the 32 byte block hash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encode_header(self, &mut encoder);
+        encoder.u64(self.transactions.len() as u64);
+        for tx in &self.transactions {
+            tx.encode(&mut encoder);
+        }
+        encoder.hash(&self.hash).finish()
+    }
+
+    /// Decodes a block written by `to_bytes`.
+    ///
+    /// The stored hash is read back as is; it is checked against the block
+    /// contents when the block is added to a chain.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Block, Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut block = match decoder.u32()? {
+            BLOCK_VERSION => decode_header(&mut decoder)?,
+            version => return Err(Error::Decode(format!("unsupported block version {}", version))),
+        };
+        for _ in 0..decoder.u64()? {
+            block.transactions.push(Transaction::decode(&mut decoder)?);
+        }
+        block.hash = decoder.hash()?;
+        decoder.finish()?;
+        Ok(block)
+    }
+}
+
+/// A change of the main chain from one branch of the block tree to another.
+#[derive(Debug,Clone)]
+pub struct Reorg {
+    /// Hash of the last block both branches have in common.
+    pub fork_point: Hash,
+    /// Blocks removed from the main chain, newest first, in the order to roll them back.
+    pub disconnected: Vec<Block>,
+    /// Blocks added to the main chain, oldest first, in the order to replay them.
+    pub connected: Vec<Block>,
+}
+
+/// What happened to a block handed to `Blockchain::insert`.
+#[derive(Debug,Clone)]
+pub enum Insertion {
+    /// The block extended the main chain.
+    Extended,
+    /// The block was kept on a side branch with less work than the main chain.
+    SideBranch,
+    /// The block's branch overtook the main chain.
+    Reorganized(Reorg),
+}
+
+/// A tree of blocks rooted at a genesis block.
+///
+/// The branch with the most work is the main chain, which the indexing and
+/// iteration methods work on. Blocks on other branches are kept by hash so
+/// that a branch can take over once it gathers more work.
+pub struct Blockchain {
+    blocks: Vec<Block>,
+    heights: HashMap<Hash, u32>,
+    txids: HashMap<Hash, u32>,
+    side: HashMap<Hash, Block>,
+    work: HashMap<Hash, u128>,
+    state: State,
+    // undo data for each main chain block, by height
+    undo: Vec<StateUndo>,
+    params: Params,
+    clock: Box<dyn Clock>,
+    tip: Arc<AtomicU64>,
+    subscribers: Vec<Sender<Reorg>>,
+    store: Option<Box<dyn BlockStore>>,
 }
 
-#[derive(Debug)]
-struct Blockchain {
-    blocks: Vec<Block>
+impl fmt::Debug for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Blockchain")
+            .field("blocks", &self.blocks)
+            .field("side", &self.side.len())
+            .field("params", &self.params)
+            .field("stored", &self.store.is_some())
+            .finish()
+    }
 }
 
 impl Blockchain {
-    pub fn new(genesis_block: Block) -> Blockchain {
-        Blockchain {
-            blocks: vec![genesis_block],
+    /// Starts a new chain from `genesis_block` whose blocks are hashed with
+    /// `algorithm`, rejecting the genesis block if its hash does not match
+    /// its contents. Other consensus rules take their default values.
+    pub fn new(genesis_block: Block, algorithm: Algorithm) -> Result<Blockchain, Error> {
+        Blockchain::with_params(genesis_block, Params { algorithm, ..Params::default() })
+    }
+
+    /// Starts a new chain from `genesis_block` that follows `params`.
+    pub fn with_params(genesis_block: Block, params: Params) -> Result<Blockchain, Error> {
+        let state = State::new(params.state_model);
+        if !is_genesis_valid(&params.algorithm, &genesis_block) || state.check(&params.algorithm, &genesis_block).is_err() {
+            warn!(hash = %genesis_block.hash, "invalid genesis block");
+            return Err(Error::InvalidGenesis);
         }
+
+        Ok(Blockchain::start(genesis_block, params))
+    }
+
+    /// Starts a new chain from the genesis block described by `spec`,
+    /// checked as `with_params` does.
+    pub fn from_spec(spec: &GenesisSpec) -> Result<Blockchain, Error> {
+        Blockchain::with_params(spec.block()?, spec.params())
+    }
+
+    /// Opens the chain kept in the block log at `path`, or starts one from
+    /// `spec` if the log is new. See `with_store`.
+    pub fn open<P: AsRef<Path>>(path: P, spec: &GenesisSpec) -> Result<Blockchain, Error> {
+        Blockchain::with_store(FileStore::open(path)?, spec)
     }
 
+    /// Loads the main chain kept in `store` and re-validates every block
+    /// under the rules of `spec`, or starts a new chain from `spec` if the
+    /// store is empty. Every later change to the main chain is written to
+    /// `store` before it takes effect.
+    pub fn with_store<S: BlockStore + 'static>(mut store: S, spec: &GenesisSpec) -> Result<Blockchain, Error> {
+        let genesis_block = spec.block()?;
+
+        let mut blockchain = if store.is_empty() {
+            let blockchain = Blockchain::with_params(genesis_block, spec.params())?;
+            store.push(blockchain.genesis())?;
+            blockchain
+        } else {
+            let blocks = store.load()?;
+            if blocks[0].hash != genesis_block.hash {
+                warn!(stored = %blocks[0].hash, spec = %genesis_block.hash, "stored chain has a different genesis block");
+                return Err(Error::GenesisMismatch);
+            }
+            Blockchain::from_blocks(spec.params(), blocks).map_err(|err| Error::InvalidChain(Box::new(err)))?
+        };
+
+        info!(blocks = blockchain.len(), "loaded chain from store");
+        blockchain.store = Some(Box::new(store));
+        Ok(blockchain)
+    }
+
+    /// Rebuilds a chain from its blocks, ordered from genesis, checking
+    /// each one as it is appended. The blocks were accepted before, so they
+    /// are not checked against `MAX_FUTURE_DRIFT` again.
+    pub fn from_blocks(params: Params, blocks: Vec<Block>) -> Result<Blockchain, Error> {
+        let mut blocks = blocks.into_iter();
+        let genesis_block = blocks.next().ok_or(Error::InvalidGenesis)?;
+
+        let mut blockchain = Blockchain::with_params(genesis_block, params)?;
+        for block in blocks {
+            blockchain.append_at(block, None)?;
+        }
+        Ok(blockchain)
+    }
+
+    /// Encodes the consensus rules and main chain blocks compactly, behind
+    /// a magic and `CHAIN_FORMAT_VERSION`.
+    ///
+    /// Side branches are not included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder
+            .u32(CHAIN_FORMAT_VERSION)
+            .bytes(self.params.algorithm.to_string().as_bytes())
+            .u64(self.params.block_time)
+            .u32(self.params.retarget_window)
+            .bytes(self.params.state_model.to_string().as_bytes())
+            .bytes(self.params.issuance.to_string().as_bytes())
+            .u64(self.blocks.len() as u64);
+        for block in &self.blocks {
+            encoder.bytes(&block.to_bytes());
+        }
+        let mut bytes = CHAIN_MAGIC.to_vec();
+        bytes.extend_from_slice(&encoder.finish());
+        bytes
+    }
+
+    /// Decodes and re-validates a chain written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Blockchain, Error> {
+        let encoded = bytes
+            .strip_prefix(&CHAIN_MAGIC[..])
+            .ok_or_else(|| Error::Decode("not an encoded chain".to_string()))?;
+        let mut decoder = Decoder::new(encoded);
+        let format = decoder.u32()?;
+        if format != CHAIN_FORMAT_VERSION {
+            return Err(Error::Decode(format!("unsupported chain format {}", format)));
+        }
+
+        let params = Params {
+            algorithm: decoder.string()?.parse().map_err(Error::Decode)?,
+            block_time: decoder.u64()?,
+            retarget_window: decoder.u32()?,
+            state_model: decoder.string()?.parse().map_err(Error::Decode)?,
+            issuance: decoder.string()?.parse().map_err(Error::Decode)?,
+        };
+
+        let count = decoder.u64()?;
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            blocks.push(Block::from_bytes(decoder.bytes()?)?);
+        }
+        decoder.finish()?;
+
+        Blockchain::from_blocks(params, blocks)
+    }
+
+    fn start(genesis_block: Block, params: Params) -> Blockchain {
+        let mut blockchain = Blockchain {
+            blocks: Vec::new(),
+            heights: HashMap::new(),
+            txids: HashMap::new(),
+            side: HashMap::new(),
+            work: HashMap::new(),
+            state: State::new(params.state_model),
+            undo: Vec::new(),
+            params,
+            clock: Box::new(SystemClock),
+            tip: Arc::new(AtomicU64::new(0)),
+            subscribers: Vec::new(),
+            store: None,
+        };
+        blockchain.work.insert(genesis_block.hash, block_work(genesis_block.difficulty));
+        blockchain.connect(genesis_block);
+        blockchain
+    }
+
+    /// Uses `clock` instead of the system clock to date and check blocks.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Returns a channel that receives every reorganization of the main chain.
+    pub fn subscribe(&mut self) -> Receiver<Reorg> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Mines a new block carrying a single transaction with `payload` as
+    /// its data and appends it to the end of the chain.
+    ///
+    /// The transaction carries the block height as its nonce, so the same
+    /// payload can be added again without repeating a transaction.
     pub fn add_block(&mut self, payload: &str) -> Result<(), Error> {
-        if let Some(prev_block) = self.blocks.last().cloned() {
-            let new_block = Block::new(&prev_block, payload);
+        let tx = Transaction { nonce: u64::from(self.latest().index + 1), ..Transaction::data(payload) };
+        self.add_transactions(vec![tx])
+    }
+
+    /// Mines a new block carrying `transactions` and appends it to the end
+    /// of the chain.
+    pub fn add_transactions(&mut self, transactions: Vec<Transaction>) -> Result<(), Error> {
+        let mut new_block = self.candidate(transactions);
+        new_block.mine(&self.params.algorithm);
+        self.append(new_block)
+    }
+
+    /// Builds the unmined block carrying `transactions` that would follow
+    /// the current tip, dated now and declaring the required difficulty.
+    pub fn candidate(&self, transactions: Vec<Transaction>) -> Block {
+        let now = self.clock.now();
+        let block = Block::new(self.latest(), transactions, now, self.next_difficulty(), &self.params.algorithm);
+
+        // a block whose transactions do not apply is rejected when appended
+        match self.state.root_after(&self.params.algorithm, &block) {
+            Ok(root) if !root.is_zero() => block.with_state_root(root, &self.params.algorithm),
+            _ => block,
+        }
+    }
+
+    /// Appends a block mined elsewhere to the end of the chain.
+    pub fn append(&mut self, new_block: Block) -> Result<(), Error> {
+        let now = self.clock.now();
+        self.append_at(new_block, Some(now))
+    }
 
-            if is_block_valid(&prev_block, &new_block) {
-                println!("Adding block to chain");
-                self.blocks.push(new_block);
+    // appends `new_block`, dating it against `now` if it is new to the node
+    fn append_at(&mut self, new_block: Block, now: Option<u64>) -> Result<(), Error> {
+        let checked = is_block_valid(&self.params, self.context(), &new_block, now)
+            .and_then(|()| self.state.check(&self.params.algorithm, &new_block))
+            .and_then(|()| self.check_unique(&new_block));
+        match checked {
+            Ok(()) => {
+                info!(index = new_block.index, hash = %new_block.hash, "adding block to chain");
+                self.persist(new_block.index, slice::from_ref(&new_block))?;
+                let work = self.total_work().saturating_add(block_work(new_block.difficulty));
+                self.work.insert(new_block.hash, work);
+                self.connect(new_block);
+                self.tip.fetch_add(1, Ordering::SeqCst);
                 Ok(())
-            } else {
-                println!("Block was invalid");
-                Err(Error::InvalidBlock)
             }
-        } else {
-            println!("Could not find previous block to compare");
-            Err(Error::InvalidBlock)
+            Err(err) => {
+                warn!(index = new_block.index, error = %err, "block was invalid");
+                Err(err)
+            }
         }
     }
 
-    pub fn replace(&mut self, new_chain: Blockchain) -> Result<(), Error> {
-        let local_len = self.blocks.len();
-        let new_len = new_chain.blocks.len();
+    /// Adds a block whose parent may be anywhere in the tree, reorganizing
+    /// the main chain if the block's branch now has the most work.
+    pub fn insert(&mut self, block: Block) -> Result<Insertion, Error> {
+        let parent = block.prev_hash;
+        if !self.contains(&parent) {
+            return Err(Error::UnknownParent { index: block.index });
+        }
 
-        if is_chain_valid(&self, &new_chain) && new_len > local_len {
-            println!("Valid chain. Replacing current chain with new one.");
-            self.blocks = new_chain.blocks;
-            Ok(())
-        } else {
-            println!("Invalid replacement chain");
-            Err(Error::InvalidChain)
+        let tip = self.attach(&parent, vec![block])?;
+        match self.adopt(&tip) {
+            Ok((_, ref reorg)) if reorg.disconnected.is_empty() => Ok(Insertion::Extended),
+            Ok((_, reorg)) => Ok(Insertion::Reorganized(reorg)),
+            Err(Error::LessWork { .. }) | Err(Error::LostTieBreak) => Ok(Insertion::SideBranch),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Switches to `new_chain` if it shares this chain's genesis block, is
+    /// valid and wins fork choice, returning the rule that decided in its
+    /// favour.
+    ///
+    /// The chain with more cumulative work wins. When both carry the same
+    /// work, the chain whose tip has the lower hash wins. Blocks of a losing
+    /// chain are kept as a side branch.
+    pub fn replace(&mut self, new_chain: Blockchain) -> Result<ForkChoice, Error> {
+        // compare genesis blocks to ensure same origin
+        if self.genesis().hash != new_chain.genesis().hash {
+            warn!(local = %self.genesis().hash, remote = %new_chain.genesis().hash, "genesis block mismatch");
+            return Err(Error::GenesisMismatch);
+        }
+
+        // skip the blocks this tree already holds
+        let mut blocks = new_chain.blocks;
+        let known = blocks.iter().take_while(|block| self.contains(&block.hash)).count();
+        let segment = blocks.split_off(known);
+        let fork_point = blocks[known - 1].hash;
+
+        self.extend_from(&fork_point, segment)
+    }
+
+    /// Adds `blocks`, a run of consecutive blocks growing from the known
+    /// block `fork_point`, and switches to them if they win fork choice.
+    ///
+    /// Only the new blocks are validated, against the ancestors already
+    /// stored, so peers can sync by sending the blocks after a common
+    /// ancestor instead of whole chains. The blocks are kept as a side
+    /// branch if they lose.
+    pub fn extend_from(&mut self, fork_point: &Hash, blocks: Vec<Block>) -> Result<ForkChoice, Error> {
+        if !self.contains(fork_point) {
+            warn!(fork_point = %fork_point, "segment grows from an unknown block");
+            return Err(Error::UnknownAncestor(*fork_point));
+        }
+
+        let tip = self.attach(fork_point, blocks).map_err(|err| {
+            warn!(error = %err, "invalid chain segment");
+            Error::InvalidChain(Box::new(err))
+        })?;
+
+        self.adopt(&tip).map(|(choice, _)| choice)
+    }
+
+    /// Checks every block of the main chain, starting at genesis, and reports
+    /// the first one that is invalid. Blocks are not checked against
+    /// `MAX_FUTURE_DRIFT` again, as they were when they were added.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !is_genesis_valid(&self.params.algorithm, self.genesis()) {
+            return Err(Error::InvalidGenesis);
+        }
+
+        let context = context_len(&self.params);
+        let mut state = State::new(self.params.state_model);
+        state.apply(self.genesis());
+        let mut txids: HashSet<Hash> = self.genesis().transactions.iter().map(Transaction::txid).collect();
+        for index in 1..self.blocks.len() {
+            let block = &self.blocks[index];
+            let ancestors = &self.blocks[index.saturating_sub(context)..index];
+            is_block_valid(&self.params, ancestors, block, None)?;
+            state.check(&self.params.algorithm, block)?;
+            for tx in &block.transactions {
+                if !txids.insert(tx.txid()) {
+                    return Err(Error::DuplicateTransaction { index: block.index, txid: tx.txid() });
+                }
+            }
+            state.apply(block);
+        }
+
+        Ok(())
+    }
+
+    // validates `segment` as a branch growing from `parent` and stores it as
+    // a side branch, returning the hash of the branch tip
+    fn attach(&mut self, parent: &Hash, segment: Vec<Block>) -> Result<Hash, Error> {
+        let now = self.clock.now();
+        let context = context_len(&self.params);
+        let mut ancestors = self.ancestors(parent, context);
+
+        for block in &segment {
+            if self.contains(&block.hash) {
+                return Err(Error::KnownBlock { index: block.index });
+            }
+            is_block_valid(&self.params, &ancestors, block, Some(now))?;
+
+            ancestors.push(block.clone());
+            if ancestors.len() > context {
+                ancestors.remove(0);
+            }
+        }
+
+        let mut tip = *parent;
+        let mut work = self.work[parent];
+        for block in segment {
+            debug!(index = block.index, hash = %block.hash, "storing block on side branch");
+            work = work.saturating_add(block_work(block.difficulty));
+            tip = block.hash;
+            self.work.insert(block.hash, work);
+            self.side.insert(block.hash, block);
+        }
+
+        Ok(tip)
+    }
+
+    // makes the branch ending in `tip` the main chain if it wins fork choice
+    fn adopt(&mut self, tip: &Hash) -> Result<(ForkChoice, Reorg), Error> {
+        let local_work = self.total_work();
+        let remote_work = self.work[tip];
+
+        match choose_fork(local_work, &self.latest().hash, remote_work, tip) {
+            Ok(choice) => {
+                info!(local_work, remote_work, tip = %tip, rule = ?choice, "switching main chain");
+                Ok((choice, self.reorganize(tip)?))
+            }
+            Err(err) => {
+                debug!(local_work, remote_work, error = %err, "keeping local chain");
+                Err(err)
+            }
+        }
+    }
+
+    fn reorganize(&mut self, tip: &Hash) -> Result<Reorg, Error> {
+        // walk back from the new tip to the main chain
+        let mut connected = Vec::new();
+        let mut cursor = *tip;
+        while let Some(block) = self.side.get(&cursor) {
+            cursor = block.prev_hash;
+            connected.push(block.clone());
+        }
+        connected.reverse();
+
+        // the new branch can only be checked against the unspent outputs
+        // at the fork point, so switch over and switch back if it fails
+        let fork_height = self.heights[&cursor];
+        let disconnected = self.disconnect_to(fork_height);
+        let switched = self
+            .connect_checked(&connected)
+            .and_then(|()| self.persist(fork_height + 1, &connected));
+        if let Err(err) = switched {
+            warn!(tip = %tip, error = %err, "branch could not be connected");
+            self.disconnect_to(fork_height);
+            for block in disconnected.into_iter().rev() {
+                self.connect(block);
+            }
+            return Err(err);
+        }
+
+        for block in &connected {
+            self.side.remove(&block.hash);
+        }
+        for block in &disconnected {
+            self.side.insert(block.hash, block.clone());
+        }
+        self.tip.fetch_add(1, Ordering::SeqCst);
+
+        let reorg = Reorg { fork_point: cursor, disconnected, connected };
+        if !reorg.disconnected.is_empty() {
+            info!(
+                fork_point = %reorg.fork_point,
+                disconnected = reorg.disconnected.len(),
+                connected = reorg.connected.len(),
+                "reorganized main chain"
+            );
+            self.subscribers.retain(|subscriber| subscriber.send(reorg.clone()).is_ok());
+        }
+        Ok(reorg)
+    }
+
+    // connects `blocks` in order, stopping at the first one that spends
+    // outputs the main chain does not hold
+    fn connect_checked(&mut self, blocks: &[Block]) -> Result<(), Error> {
+        for block in blocks {
+            self.state.check(&self.params.algorithm, block)?;
+            self.check_unique(block)?;
+            self.connect(block.clone());
+        }
+        Ok(())
+    }
+
+    // rejects a block repeating a main chain transaction, whose outputs and
+    // index entry it would take over and then take away again if rolled back
+    fn check_unique(&self, block: &Block) -> Result<(), Error> {
+        match block.transactions.iter().map(Transaction::txid).find(|txid| self.txids.contains_key(txid)) {
+            Some(txid) => Err(Error::DuplicateTransaction { index: block.index, txid }),
+            None => Ok(()),
+        }
+    }
+
+    // disconnects main chain blocks down to `height`, newest first
+    fn disconnect_to(&mut self, height: u32) -> Vec<Block> {
+        let mut disconnected = Vec::new();
+        while self.latest().index > height {
+            disconnected.push(self.disconnect());
+        }
+        disconnected
+    }
+
+    // writes main chain blocks from height `from` onwards to the store, if any
+    fn persist(&mut self, from: u32, blocks: &[Block]) -> Result<(), Error> {
+        match self.store {
+            Some(ref mut store) => store.rewrite(from, blocks),
+            None => Ok(()),
+        }
+    }
+
+    fn connect(&mut self, block: Block) {
+        self.undo.push(self.state.apply(&block));
+        self.heights.insert(block.hash, block.index);
+        for tx in &block.transactions {
+            self.txids.insert(tx.txid(), block.index);
+        }
+        self.blocks.push(block);
+    }
+
+    fn disconnect(&mut self) -> Block {
+        let block = self.blocks.pop().expect("genesis block is never disconnected");
+        let undo = self.undo.pop().expect("every main chain block has undo data");
+        self.state.undo(&block, undo);
+        self.heights.remove(&block.hash);
+        for tx in &block.transactions {
+            self.txids.remove(&tx.txid());
+        }
+        block
+    }
+
+    // the main chain blocks needed to check a block following the tip
+    fn context(&self) -> &[Block] {
+        let start = self.blocks.len().saturating_sub(context_len(&self.params));
+        &self.blocks[start..]
+    }
+
+    // up to `count` blocks ending with the known block `hash`, oldest first
+    fn ancestors(&self, hash: &Hash, count: usize) -> Vec<Block> {
+        let mut branch = Vec::new();
+        let mut cursor = *hash;
+        while branch.len() < count {
+            match self.side.get(&cursor) {
+                Some(block) => {
+                    cursor = block.prev_hash;
+                    branch.push(block.clone());
+                }
+                None => break,
+            }
+        }
+
+        let mut ancestors = Vec::with_capacity(count);
+        if branch.len() < count {
+            if let Some(&height) = self.heights.get(&cursor) {
+                let end = height as usize + 1;
+                let start = end.saturating_sub(count - branch.len());
+                ancestors.extend_from_slice(&self.blocks[start..end]);
+            }
         }
+        ancestors.extend(branch.into_iter().rev());
+        ancestors
+    }
+
+    /// Whether the block with `hash` is anywhere in the tree.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.heights.contains_key(hash) || self.side.contains_key(hash)
+    }
+
+    /// Number of blocks on the main chain, including the genesis block.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether the chain holds no blocks at all.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// All main chain blocks, ordered from genesis to the latest block.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Iterates over the main chain from genesis to the latest block.
+    pub fn iter(&self) -> slice::Iter<'_, Block> {
+        self.blocks.iter()
+    }
+
+    /// Looks up a main chain block by its index.
+    pub fn get(&self, index: u32) -> Option<&Block> {
+        self.blocks.get(index as usize)
+    }
+
+    /// Looks up a main chain block by its hash.
+    pub fn get_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.heights.get(hash).map(|&height| &self.blocks[height as usize])
+    }
+
+    /// Looks up a transaction on the main chain by its id, along with the
+    /// block that carries it.
+    pub fn get_transaction(&self, txid: &Hash) -> Option<(&Block, &Transaction)> {
+        let block = &self.blocks[*self.txids.get(txid)? as usize];
+        let tx = block.transactions.iter().find(|tx| tx.txid() == *txid)?;
+        Some((block, tx))
+    }
+
+    /// Outputs paid to `address` on the main chain that no later transaction
+    /// spends, oldest first.
+    pub fn unspent_outputs(&self, address: &str) -> Vec<(OutPoint, Output)> {
+        self.state.unspent_outputs(address)
+    }
+
+    /// Coins held by `address` after the latest main chain block.
+    pub fn balance(&self, address: &str) -> u64 {
+        self.state.balance(address)
+    }
+
+    /// The nonce the next transaction sent from `address` must carry on an
+    /// account chain.
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        self.state.account(address).map_or(0, |account| account.nonce)
+    }
+
+    /// Coins in circulation after the latest main chain block: the genesis
+    /// allocations and block rewards, less any fees no coinbase collected.
+    pub fn total_supply(&self) -> u64 {
+        self.state.supply()
+    }
+
+    /// The coins held after the latest main chain block.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Builds the proof that the main chain transaction with `txid` is part
+    /// of its block, to check against that block's Merkle root.
+    pub fn prove_transaction(&self, txid: &Hash) -> Option<MerkleProof> {
+        let (block, _) = self.get_transaction(txid)?;
+        block.prove_transaction(&self.params.algorithm, txid)
+    }
+
+    /// Looks up a block kept on a side branch by its hash.
+    pub fn get_side_block(&self, hash: &Hash) -> Option<&Block> {
+        self.side.get(hash)
+    }
+
+    /// The hash function used for this chain's blocks.
+    pub fn algorithm(&self) -> Algorithm {
+        self.params.algorithm
+    }
+
+    /// The consensus rules this chain follows.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Returns a watch that goes stale once the chain moves to a new tip,
+    /// used to abandon mining on an outdated parent.
+    pub fn watch_tip(&self) -> TipWatch {
+        TipWatch::new(self.tip.clone())
+    }
+
+    /// Cumulative proof of work of every block in the main chain.
+    pub fn total_work(&self) -> u128 {
+        self.work[&self.latest().hash]
+    }
+
+    /// Difficulty the next block appended to the chain must declare.
+    pub fn next_difficulty(&self) -> u32 {
+        next_difficulty(&self.params, self.context())
+    }
+
+    /// The first block of the chain.
+    pub fn genesis(&self) -> &Block {
+        &self.blocks[0]
+    }
+
+    /// The most recently added block of the main chain.
+    pub fn latest(&self) -> &Block {
+        &self.blocks[self.blocks.len() - 1]
+    }
+}
+
+impl<'a> IntoIterator for &'a Blockchain {
+    type Item = &'a Block;
+    type IntoIter = slice::Iter<'a, Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.blocks.iter()
+    }
+}
+
+// a chain is written as its params and main chain blocks, and re-validated
+// block by block when read back
+#[cfg(feature = "serde")]
+impl Serialize for Blockchain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Blockchain", 2)?;
+        state.serialize_field("params", &self.params)?;
+        state.serialize_field("blocks", &self.blocks)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Blockchain {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Blockchain, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Blockchain")]
+        struct Fields {
+            params: Params,
+            blocks: Vec<Block>,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        Blockchain::from_blocks(fields.params, fields.blocks).map_err(de::Error::custom)
     }
 }
 
 pub fn run() {
     println!("Testing chain ...");
 
-    let genesis = Block {
-        index: 0,
-        timestamp: 0,
-        prev_hash: String::new(),
-        payload: "Genesis block baby!".to_string(),
-        hash: String::new(),
-    };
-
-    let mut blockchain: Blockchain = Blockchain::new(genesis);
+    let mut blockchain: Blockchain = Blockchain::from_spec(&GenesisSpec::default()).expect("the default spec is valid");
 
     let result = blockchain.add_block("Second block baby!");
 
     println!("{:?}", blockchain);
     println!("{:?}", result);
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+    use hash::{Blake3, Sha256};
+    use std::env;
+    use std::error::Error as StdError;
+    use std::fs;
+    use std::process;
+    use keys::{Keypair, Scheme};
+    use genesis::Allocation;
+    use transaction::{OutPoint, Output};
+    use test_utils::allocate;
+
+    fn data(payload: &str) -> Vec<Transaction> {
+        vec![Transaction::data(payload)]
+    }
+
+    fn genesis() -> Block {
+        Block::genesis(data("Genesis block baby!"), 0, 0, &Algorithm::Sha256)
+    }
+
+    #[test]
+    fn lookups_find_added_blocks() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.add_block("Second block baby!").unwrap();
+        blockchain.add_block("Third block baby!").unwrap();
+
+        assert_eq!(blockchain.len(), 3);
+        assert_eq!(blockchain.genesis().payload(), "Genesis block baby!");
+        assert_eq!(blockchain.latest().payload(), "Third block baby!");
+
+        let second = blockchain.get(1).unwrap();
+        assert_eq!(second.index(), 1);
+        assert_eq!(second.prev_hash(), blockchain.genesis().hash());
+        assert_eq!(blockchain.get_by_hash(second.hash()).unwrap().index(), 1);
+        assert!(blockchain.get(3).is_none());
+
+        let indexes: Vec<u32> = blockchain.iter().map(Block::index).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn genesis_hash_covers_contents() {
+        let block = genesis();
+        assert!(!block.hash().is_zero());
+        assert!(Blockchain::new(block.clone(), Algorithm::Sha256).is_ok());
+
+        let mut tampered = block;
+        tampered.transactions[0].data = "Tampered genesis".to_string();
+        match Blockchain::new(tampered, Algorithm::Sha256) {
+            Err(Error::InvalidGenesis) => {}
+            other => panic!("expected InvalidGenesis, got {:?}", other),
+        }
+
+        // no block after it could meet a difficulty above the maximum
+        let unreachable = Block::genesis(data("Genesis block baby!"), 0, MAX_DIFFICULTY + 1, &Sha256);
+        assert!(matches!(Blockchain::new(unreachable, Algorithm::Sha256), Err(Error::InvalidGenesis)));
+    }
+
+    #[test]
+    fn replace_accepts_longer_valid_chain() {
+        let mut local = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        local.add_block("local").unwrap();
+
+        let mut remote = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        for payload in &["one", "two", "three"] {
+            remote.add_block(payload).unwrap();
+        }
+        assert!(remote.validate().is_ok());
+
+        assert_eq!(local.replace(remote).unwrap(), ForkChoice::MoreWork);
+        assert_eq!(local.len(), 4);
+        assert_eq!(local.latest().payload(), "three");
+    }
+
+    #[test]
+    fn validate_names_first_failing_block() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        for payload in &["one", "two", "three"] {
+            blockchain.add_block(payload).unwrap();
+        }
+
+        blockchain.blocks[2].transactions[0].data = "forged".to_string();
+        assert!(matches!(blockchain.validate(), Err(Error::MerkleMismatch { index: 2 })));
+
+        blockchain.blocks[2].merkle_root = transactions_root(&Sha256, &blockchain.blocks[2].transactions);
+        assert!(matches!(blockchain.validate(), Err(Error::HashMismatch { index: 2 })));
+
+        blockchain.blocks[2].timestamp = 0;
+        assert!(matches!(blockchain.validate(), Err(Error::TimestampRegression { index: 2 })));
+
+        blockchain.blocks[1].index = 5;
+        assert!(matches!(blockchain.validate(), Err(Error::IndexMismatch { expected: 1, found: 5 })));
+    }
+
+    #[test]
+    fn replace_reports_cause_of_rejection() {
+        let mut local = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        local.add_block("local").unwrap();
+
+        let remote = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        match local.replace(remote) {
+            Err(Error::LessWork { local: 2, remote: 1 }) => {}
+            other => panic!("expected LessWork, got {:?}", other),
+        }
+
+        let mut forged = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        forged.add_block("one").unwrap();
+        forged.add_block("two").unwrap();
+        forged.blocks[1].transactions[0].data = "forged".to_string();
+        let err = local.replace(forged).unwrap_err();
+        let source = err.source().and_then(|source| source.downcast_ref::<Error>());
+        assert!(matches!(source, Some(&Error::MerkleMismatch { index: 1 })));
+
+        let other = Blockchain::new(Block::genesis(data("Other genesis"), 0, 0, &Algorithm::Sha256), Algorithm::Sha256).unwrap();
+        assert!(matches!(local.replace(other), Err(Error::GenesisMismatch)));
+    }
+
+    #[test]
+    fn timestamps_come_from_the_clock() {
+        let clock = MockClock::new(1_000);
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.set_clock(clock.clone());
+
+        blockchain.add_block("one").unwrap();
+        clock.advance(500);
+        blockchain.add_block("two").unwrap();
+
+        assert_eq!(blockchain.get(1).unwrap().timestamp(), 1_000);
+        assert_eq!(blockchain.get(2).unwrap().timestamp(), 1_500);
+    }
+
+    #[test]
+    fn rejects_blocks_from_the_future() {
+        let clock = MockClock::new(1_000);
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.set_clock(clock.clone());
+
+        let block = Block::new(blockchain.latest(), data("early"), 1_000 + MAX_FUTURE_DRIFT + 1, 0, &Sha256);
+        assert!(matches!(
+            is_block_valid(&blockchain.params, &blockchain.blocks, &block, Some(clock.now())),
+            Err(Error::TimestampTooFar { index: 1 })
+        ));
+
+        let block = Block::new(blockchain.latest(), data("on time"), 1_000 + MAX_FUTURE_DRIFT, 0, &Sha256);
+        assert!(is_block_valid(&blockchain.params, &blockchain.blocks, &block, Some(clock.now())).is_ok());
+    }
+
+    #[test]
+    fn reloaded_blocks_are_not_dated_against_the_clock() {
+        let clock = MockClock::new(MAX_FUTURE_DRIFT + 1_000);
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.set_clock(clock.clone());
+        blockchain.add_block("one").unwrap();
+        blockchain.add_block("two").unwrap();
+
+        // the clock is set back after the blocks were accepted
+        clock.set(0);
+        assert!(blockchain.validate().is_ok());
+        let mut reloaded = Blockchain::from_blocks(blockchain.params, blockchain.blocks.clone()).unwrap();
+        reloaded.set_clock(clock.clone());
+        assert!(reloaded.validate().is_ok());
+
+        // new blocks are still held to it
+        let block = Block::new(reloaded.latest(), data("early"), MAX_FUTURE_DRIFT + 1_000, 0, &Sha256);
+        assert!(matches!(reloaded.append(block), Err(Error::TimestampTooFar { index: 3 })));
+    }
+
+    #[test]
+    fn rejects_blocks_older_than_median_time_past() {
+        let clock = MockClock::new(0);
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.set_clock(clock.clone());
+        for _ in 0..4 {
+            clock.advance(100);
+            blockchain.add_block("tick").unwrap();
+        }
+
+        // timestamps are 0, 100, 200, 300, 400 so the median is 200
+        let stale = Block::new(blockchain.latest(), data("stale"), 199, 0, &Sha256);
+        assert!(matches!(
+            is_block_valid(&blockchain.params, &blockchain.blocks, &stale, Some(clock.now())),
+            Err(Error::TimestampRegression { index: 5 })
+        ));
+
+        // a block may be older than its parent as long as it follows the median
+        let skewed = Block::new(blockchain.latest(), data("skewed"), 200, 0, &Sha256);
+        assert!(is_block_valid(&blockchain.params, &blockchain.blocks, &skewed, Some(clock.now())).is_ok());
+    }
+
+    #[test]
+    fn hash_encoding_is_unambiguous() {
+        let a = Block::with_hash(&Sha256, 1, 23, 0, Hash::zero(), data("payload"));
+        let b = Block::with_hash(&Sha256, 12, 3, 0, Hash::zero(), data("payload"));
+        assert_ne!(a.hash(), b.hash());
+
+        let mut unknown = a.clone();
+        unknown.version = BLOCK_VERSION + 1;
+        assert!(matches!(
+            calc_hash(&unknown, &Sha256),
+            Err(Error::UnsupportedVersion { index: 1, version }) if version == BLOCK_VERSION + 1
+        ));
+
+        assert!(matches!(Block::from_bytes(&unknown.to_bytes()), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn genesis_must_match_chain_algorithm() {
+        let block = Block::genesis(data("Genesis block baby!"), 0, 0, &Blake3);
+        assert!(Blockchain::new(block.clone(), Algorithm::Blake3).is_ok());
+        assert!(matches!(Blockchain::new(block, Algorithm::Sha256), Err(Error::InvalidGenesis)));
+
+        let spec = GenesisSpec { algorithm: Algorithm::Sha3_256, ..GenesisSpec::default() };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        blockchain.add_block("one").unwrap();
+        assert!(blockchain.validate().is_ok());
+        assert_ne!(blockchain.genesis().hash(), genesis().hash());
+    }
+
+    #[test]
+    fn mined_blocks_meet_their_difficulty() {
+        let mut blockchain = Blockchain::new(Block::genesis(data("Genesis"), 0, 8, &Sha256), Algorithm::Sha256).unwrap();
+        blockchain.add_block("one").unwrap();
+
+        let block = blockchain.latest().clone();
+        assert_eq!(block.difficulty(), 8);
+        assert!(block.hash().meets_difficulty(8));
+
+        // an unmined block fails the proof of work check
+        let mut lazy = Block::new(blockchain.genesis(), data("lazy"), block.timestamp(), 8, &Sha256);
+        while lazy.hash().meets_difficulty(8) {
+            lazy = Block::new(blockchain.genesis(), data("lazy"), lazy.timestamp() + 1, 8, &Sha256);
+        }
+        assert!(matches!(
+            is_block_valid(&blockchain.params, &blockchain.blocks[..1], &lazy, Some(block.timestamp())),
+            Err(Error::InsufficientWork { index: 1 })
+        ));
+
+        // the declared difficulty must follow the retargeting rules
+        let mut cheap = Block::new(blockchain.genesis(), data("cheap"), block.timestamp(), 0, &Sha256);
+        cheap.mine(&Sha256);
+        assert!(matches!(
+            is_block_valid(&blockchain.params, &blockchain.blocks[..1], &cheap, Some(block.timestamp())),
+            Err(Error::DifficultyMismatch { index: 1, expected: 8, found: 0 })
+        ));
+    }
+
+    #[test]
+    fn difficulty_retargets_to_block_time() {
+        let params = Params { algorithm: Algorithm::Sha256, block_time: 1_000, retarget_window: 4, ..Params::default() };
+        let clock = MockClock::new(0);
+        let mut blockchain = Blockchain::with_params(genesis(), params).unwrap();
+        blockchain.set_clock(clock.clone());
+
+        // blocks arriving far faster than intended raise the difficulty
+        for _ in 0..3 {
+            clock.advance(10);
+            blockchain.add_block("fast").unwrap();
+        }
+        assert_eq!(blockchain.next_difficulty(), 1);
+        clock.advance(10);
+        blockchain.add_block("fast").unwrap();
+        assert_eq!(blockchain.latest().difficulty(), 1);
+
+        // blocks arriving far slower lower it again
+        for _ in 0..4 {
+            clock.advance(10_000);
+            blockchain.add_block("slow").unwrap();
+        }
+        assert_eq!(blockchain.next_difficulty(), 0);
+        assert!(blockchain.validate().is_ok());
+    }
+
+    // a copy of `chain` running on the system clock
+    fn copy(chain: &Blockchain) -> Blockchain {
+        let mut copy = Blockchain::with_params(chain.genesis().clone(), chain.params).unwrap();
+        for block in &chain.blocks[1..] {
+            copy.append(block.clone()).unwrap();
+        }
+        copy
+    }
+
+    #[test]
+    fn more_work_beats_more_blocks() {
+        let params = Params { algorithm: Algorithm::Sha256, block_time: 1_000, retarget_window: 2, ..Params::default() };
+
+        // fast blocks drive the difficulty up
+        let clock = MockClock::new(0);
+        let mut heavy = Blockchain::with_params(genesis(), params).unwrap();
+        heavy.set_clock(clock.clone());
+        for _ in 0..6 {
+            clock.advance(1);
+            heavy.add_block("heavy").unwrap();
+        }
+
+        // slow blocks keep it at zero
+        let clock = MockClock::new(0);
+        let mut light = Blockchain::with_params(genesis(), params).unwrap();
+        light.set_clock(clock.clone());
+        for _ in 0..8 {
+            clock.advance(10_000);
+            light.add_block("light").unwrap();
+        }
+
+        assert!(heavy.len() < light.len());
+        assert!(heavy.total_work() > light.total_work());
+
+        assert!(matches!(copy(&heavy).replace(copy(&light)), Err(Error::LessWork { .. })));
+        assert_eq!(light.replace(heavy).unwrap(), ForkChoice::MoreWork);
+        assert_eq!(light.len(), 7);
+    }
+
+    #[test]
+    fn equal_work_is_settled_by_tip_hash() {
+        let mut a = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        a.add_block("a").unwrap();
+        let mut b = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        b.add_block("b").unwrap();
+        assert_eq!(a.total_work(), b.total_work());
+
+        let (mut winner, mut loser) = if a.latest().hash() < b.latest().hash() { (a, b) } else { (b, a) };
+        let winning_tip = *winner.latest().hash();
+
+        assert!(matches!(winner.replace(copy(&loser)), Err(Error::LostTieBreak)));
+        assert!(matches!(winner.replace(copy(&winner)), Err(Error::LostTieBreak)));
+
+        assert_eq!(loser.replace(winner).unwrap(), ForkChoice::TieBreak);
+        assert_eq!(*loser.latest().hash(), winning_tip);
+    }
+
+    // a mined block following `parent`
+    fn child(parent: &Block, payload: &str) -> Block {
+        let mut block = Block::new(parent, data(payload), parent.timestamp + 1, 0, &Sha256);
+        block.mine(&Sha256);
+        block
+    }
+
+    #[test]
+    fn side_branch_overtakes_main_chain() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        let reorgs = blockchain.subscribe();
+        blockchain.add_block("a1").unwrap();
+        blockchain.add_block("a2").unwrap();
+        let a1 = blockchain.blocks[1].clone();
+        let a2 = blockchain.blocks[2].clone();
+
+        let b1 = child(blockchain.genesis(), "b1");
+        assert!(matches!(blockchain.insert(b1.clone()), Ok(Insertion::SideBranch)));
+        assert_eq!(blockchain.get_side_block(b1.hash()).unwrap().payload(), "b1");
+
+        // an equal-work branch only wins with a lower tip hash, so pick one that loses
+        let mut b2 = child(&b1, "b2");
+        while b2.hash() < a2.hash() {
+            b2 = Block::new(&b1, data("b2"), b2.timestamp() + 1, 0, &Sha256);
+            b2.mine(&Sha256);
+        }
+        assert!(matches!(blockchain.insert(b2.clone()), Ok(Insertion::SideBranch)));
+        assert!(matches!(blockchain.insert(b2.clone()), Err(Error::KnownBlock { index: 2 })));
+
+        let b3 = child(&b2, "b3");
+        let reorg = match blockchain.insert(b3.clone()).unwrap() {
+            Insertion::Reorganized(reorg) => reorg,
+            other => panic!("expected a reorganization, got {:?}", other),
+        };
+
+        let hashes = |blocks: &[Block]| blocks.iter().map(|block| *block.hash()).collect::<Vec<_>>();
+        assert_eq!(reorg.fork_point, *blockchain.genesis().hash());
+        assert_eq!(hashes(&reorg.disconnected), vec![*a2.hash(), *a1.hash()]);
+        assert_eq!(hashes(&reorg.connected), vec![*b1.hash(), *b2.hash(), *b3.hash()]);
+        assert_eq!(hashes(&reorgs.try_recv().unwrap().connected), hashes(&reorg.connected));
+
+        assert_eq!(blockchain.latest().hash(), b3.hash());
+        assert_eq!(blockchain.get_by_hash(b1.hash()).unwrap().index(), 1);
+        assert!(blockchain.get_by_hash(a1.hash()).is_none());
+        assert!(blockchain.get_side_block(a1.hash()).is_some());
+        assert!(blockchain.validate().is_ok());
+
+        // extending the new tip is a plain extension
+        let b4 = child(&b3, "b4");
+        assert!(matches!(blockchain.insert(b4), Ok(Insertion::Extended)));
+        assert!(reorgs.try_recv().is_err());
+    }
+
+    #[test]
+    fn insert_rejects_orphans() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        let stranger = Blockchain::new(Block::genesis(data("Other genesis"), 0, 0, &Sha256), Algorithm::Sha256).unwrap();
+        let orphan = child(stranger.genesis(), "orphan");
+        assert!(matches!(blockchain.insert(orphan), Err(Error::UnknownParent { index: 1 })));
+    }
+
+    #[test]
+    fn extend_from_applies_segment_after_known_ancestor() {
+        let mut local = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        local.add_block("shared").unwrap();
+        local.add_block("local").unwrap();
+        let shared = local.blocks[1].clone();
+
+        let r2 = child(&shared, "r2");
+        let r3 = child(&r2, "r3");
+        assert_eq!(local.extend_from(shared.hash(), vec![r2.clone(), r3.clone()]).unwrap(), ForkChoice::MoreWork);
+        assert_eq!(local.latest().hash(), r3.hash());
+        assert_eq!(local.len(), 4);
+
+        // a segment that loses fork choice is kept as a side branch
+        let s2 = child(&shared, "s2");
+        assert!(matches!(local.extend_from(shared.hash(), vec![s2.clone()]), Err(Error::LessWork { .. })));
+        assert!(local.get_side_block(s2.hash()).is_some());
+    }
+
+    #[test]
+    fn extend_from_rejects_unknown_ancestors_and_bad_segments() {
+        let mut local = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        let stranger = Blockchain::new(Block::genesis(data("Other genesis"), 0, 0, &Sha256), Algorithm::Sha256).unwrap();
+        let orphan = child(stranger.genesis(), "orphan");
+        match local.extend_from(stranger.genesis().hash(), vec![orphan]) {
+            Err(Error::UnknownAncestor(hash)) => assert_eq!(hash, *stranger.genesis().hash()),
+            other => panic!("expected UnknownAncestor, got {:?}", other),
+        }
+
+        // the second block does not follow the first
+        let first = child(local.genesis(), "first");
+        let detached = child(local.genesis(), "detached");
+        let genesis_hash = *local.genesis().hash();
+        let err = local.extend_from(&genesis_hash, vec![first, detached]).unwrap_err();
+        let source = err.source().and_then(|source| source.downcast_ref::<Error>());
+        assert!(matches!(source, Some(&Error::IndexMismatch { expected: 2, found: 1 })));
+        assert_eq!(local.len(), 1);
+    }
+
+    #[test]
+    fn blocks_carry_checked_transactions() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        let note = Transaction::data("note");
+        blockchain.add_transactions(vec![note.clone(), Transaction { nonce: 1, ..note.clone() }]).unwrap();
+
+        let (block, tx) = blockchain.get_transaction(&note.txid()).unwrap();
+        assert_eq!(block.index(), 1);
+        assert_eq!(*tx, note);
+
+        let unfunded = Transaction {
+            outputs: vec![Output { recipient: "mallory".to_string(), amount: 1_000 }],
+            ..Transaction::default()
+        };
+        let txid = unfunded.txid();
+        // only a block's first transaction may pay out coins from nowhere
+        match blockchain.add_transactions(vec![Transaction::data("first"), unfunded]) {
+            Err(Error::InvalidTransaction { index: 2, txid: found, .. }) => assert_eq!(found, txid),
+            other => panic!("expected InvalidTransaction, got {:?}", other),
+        }
+
+        let duplicate = blockchain.add_transactions(vec![Transaction::data("twice"), Transaction::data("twice")]);
+        assert!(matches!(duplicate, Err(Error::DuplicateTransaction { index: 2, .. })));
+
+        // nor may a later block repeat one, taking over its index entry
+        let repeated = blockchain.add_transactions(vec![note.clone()]);
+        assert!(matches!(repeated, Err(Error::DuplicateTransaction { index: 2, .. })));
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.get_transaction(&note.txid()).unwrap().0.index(), 1);
+    }
+
+    #[test]
+    fn spending_requires_a_signature() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let address = keypair.public_key().address();
+        let genesis = allocate(&address, 10);
+        let coin = OutPoint { txid: genesis.transactions()[0].txid(), index: 0 };
+        let mut blockchain = Blockchain::new(genesis, Algorithm::Sha256).unwrap();
+        let mut payment = Transaction {
+            inputs: vec![coin],
+            outputs: vec![
+                Output { recipient: "bob".to_string(), amount: 5 },
+                Output { recipient: address.clone(), amount: 5 },
+            ],
+            ..Transaction::default()
+        };
+
+        let txid = payment.txid();
+        match blockchain.add_transactions(vec![payment.clone()]) {
+            Err(Error::InvalidSignature { index: 1, txid: found }) => assert_eq!(found, txid),
+            other => panic!("expected InvalidSignature, got {:?}", other),
+        }
+
+        payment.sign(&keypair);
+        blockchain.add_transactions(vec![payment]).unwrap();
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.balance("bob"), 5);
+
+        // unspent output chains do not keep account balances
+        let mut account = Transaction {
+            sender: Some(address),
+            outputs: vec![Output { recipient: "bob".to_string(), amount: 5 }],
+            ..Transaction::default()
+        };
+        account.sign(&keypair);
+        assert!(matches!(blockchain.add_transactions(vec![account]), Err(Error::InvalidTransaction { index: 2, .. })));
+    }
+
+    #[test]
+    fn coinbase_collects_at_most_the_fees() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&keypair.public_key().address(), 10);
+        let coin = OutPoint { txid: genesis.transactions()[0].txid(), index: 0 };
+        let mut blockchain = Blockchain::new(genesis, Algorithm::Sha256).unwrap();
+        let mut payment = Transaction { inputs: vec![coin], outputs: vec![Output { recipient: "bob".to_string(), amount: 8 }], fee: 2, ..Transaction::default() };
+        payment.sign(&keypair);
+
+        let greedy = vec![Transaction::coinbase(1, "miner", 3), payment.clone()];
+        assert!(matches!(blockchain.add_transactions(greedy), Err(Error::CoinbaseTooLarge { index: 1, allowed: 2, found: 3 })));
+
+        let misplaced = vec![payment.clone(), Transaction::coinbase(1, "miner", 2)];
+        assert!(matches!(blockchain.add_transactions(misplaced), Err(Error::InvalidTransaction { index: 1, .. })));
+
+        blockchain.add_transactions(vec![Transaction::coinbase(1, "miner", 2), payment]).unwrap();
+        assert_eq!(blockchain.balance("miner"), 2);
+    }
+
+    #[test]
+    fn rewards_follow_the_issuance_schedule() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let alice = keypair.public_key().address();
+        let spec = GenesisSpec {
+            issuance: Issuance::Halving { reward: 50, interval: 2 },
+            allocations: vec![Allocation { address: alice.clone(), amount: 10 }],
+            ..GenesisSpec::default()
+        };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        assert_eq!(blockchain.total_supply(), 10);
+
+        blockchain.add_transactions(vec![Transaction::coinbase(1, "miner", 50)]).unwrap();
+        assert_eq!(blockchain.total_supply(), 60);
+
+        // the reward halves at height 2, though fees can still be collected
+        let coin = OutPoint { txid: blockchain.genesis().transactions()[0].txid(), index: 0 };
+        let mut payment = Transaction { inputs: vec![coin], outputs: vec![Output { recipient: "bob".to_string(), amount: 7 }], fee: 3, ..Transaction::default() };
+        payment.sign(&keypair);
+        let greedy = blockchain.add_transactions(vec![Transaction::coinbase(2, "miner", 50), payment.clone()]);
+        assert!(matches!(greedy, Err(Error::CoinbaseTooLarge { index: 2, allowed: 28, found: 50 })));
+
+        blockchain.add_transactions(vec![Transaction::coinbase(2, "miner", 28), payment]).unwrap();
+        assert_eq!(blockchain.balance("miner"), 78);
+        assert_eq!(blockchain.total_supply(), 85);
+
+        let decoded = Blockchain::from_bytes(&blockchain.to_bytes()).unwrap();
+        assert_eq!(decoded.params().issuance, spec.issuance);
+        assert_eq!(decoded.total_supply(), 85);
+    }
+
+    #[test]
+    fn coinbases_carry_their_block_height() {
+        let spec = GenesisSpec { issuance: Issuance::Fixed { reward: 50 }, ..GenesisSpec::default() };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        let coinbase = Transaction::coinbase(1, "miner", 50);
+        blockchain.add_transactions(vec![coinbase.clone()]).unwrap();
+
+        // a coinbase reused from another height would share its id
+        match blockchain.add_transactions(vec![coinbase.clone()]) {
+            Err(Error::InvalidTransaction { index: 2, txid, .. }) => assert_eq!(txid, coinbase.txid()),
+            other => panic!("expected InvalidTransaction, got {:?}", other),
+        }
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.total_supply(), 50);
+        assert_eq!(blockchain.get_transaction(&coinbase.txid()).unwrap().0.index(), 1);
+    }
+
+    #[test]
+    fn double_spends_are_rejected_across_reorgs() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&keypair.public_key().address(), 10);
+        let coin = OutPoint { txid: genesis.transactions()[0].txid(), index: 0 };
+        let mut blockchain = Blockchain::new(genesis, Algorithm::Sha256).unwrap();
+
+        let mut to_bob = Transaction { inputs: vec![coin], outputs: vec![Output { recipient: "bob".to_string(), amount: 10 }], ..Transaction::default() };
+        to_bob.sign(&keypair);
+        let mut to_carol = Transaction { outputs: vec![Output { recipient: "carol".to_string(), amount: 10 }], ..to_bob.clone() };
+        to_carol.sign(&keypair);
+
+        blockchain.add_transactions(vec![to_bob]).unwrap();
+        match blockchain.add_transactions(vec![to_carol.clone()]) {
+            Err(Error::MissingOutput { index: 2, input, .. }) => assert_eq!(input, coin),
+            other => panic!("expected MissingOutput, got {:?}", other),
+        }
+
+        // a branch spending the same output elsewhere takes over and
+        // rolls back the payment to bob
+        let mut c1 = Block::new(blockchain.genesis(), vec![to_carol.clone()], 1, 0, &Sha256);
+        c1.mine(&Sha256);
+        while c1.hash() < blockchain.latest().hash() {
+            c1 = Block::new(blockchain.genesis(), vec![to_carol.clone()], c1.timestamp() + 1, 0, &Sha256);
+            c1.mine(&Sha256);
+        }
+        let c2 = child(&c1, "c2");
+        assert!(matches!(blockchain.insert(c1.clone()), Ok(Insertion::SideBranch)));
+        assert!(matches!(blockchain.insert(c2.clone()), Ok(Insertion::Reorganized(_))));
+        assert_eq!(blockchain.balance("bob"), 0);
+        assert_eq!(blockchain.balance("carol"), 10);
+        assert!(blockchain.validate().is_ok());
+
+        // a branch that spends it twice is kept aside, then refused once it
+        // has more work
+        let mut d2 = Block::new(&c1, vec![to_carol], c1.timestamp() + 1, 0, &Sha256);
+        d2.mine(&Sha256);
+        while d2.hash() < c2.hash() {
+            d2 = Block::new(&c1, d2.transactions().to_vec(), d2.timestamp() + 1, 0, &Sha256);
+            d2.mine(&Sha256);
+        }
+        assert!(matches!(blockchain.insert(d2.clone()), Ok(Insertion::SideBranch)));
+        assert!(matches!(blockchain.insert(child(&d2, "d3")), Err(Error::MissingOutput { index: 2, .. })));
+        assert_eq!(blockchain.latest().hash(), c2.hash());
+        assert_eq!(blockchain.balance("carol"), 10);
+    }
+
+    #[test]
+    fn account_chains_commit_to_their_state() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let alice = keypair.public_key().address();
+        let spec = GenesisSpec {
+            state_model: StateModel::Account,
+            allocations: vec![Allocation { address: alice.clone(), amount: 10 }],
+            ..GenesisSpec::default()
+        };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+
+        let mut payment = Transaction {
+            sender: Some(alice.clone()),
+            outputs: vec![Output { recipient: "bob".to_string(), amount: 4 }],
+            fee: 1,
+            ..Transaction::default()
+        };
+        payment.sign(&keypair);
+
+        let block = blockchain.candidate(vec![payment.clone()]);
+        let mut forged = block.clone().with_state_root(Sha256.digest(b"forged"), &Sha256);
+        forged.mine(&Sha256);
+        assert!(matches!(blockchain.append(forged), Err(Error::StateRootMismatch { index: 1 })));
+
+        let mut block = block;
+        block.mine(&Sha256);
+        blockchain.append(block).unwrap();
+        assert_eq!(blockchain.balance("bob"), 4);
+        assert_eq!(blockchain.balance(&alice), 5);
+        assert_eq!(blockchain.next_nonce(&alice), 1);
+        match *blockchain.state() {
+            State::Account(ref accounts) => assert_eq!(accounts.root(&Sha256), *blockchain.latest().state_root()),
+            ref other => panic!("expected account state, got {:?}", other),
+        }
+
+        let replayed = blockchain.add_transactions(vec![payment]);
+        assert!(matches!(replayed, Err(Error::NonceMismatch { index: 2, expected: 1, found: 0, .. })));
+
+        let decoded = Blockchain::from_bytes(&blockchain.to_bytes()).unwrap();
+        assert_eq!(decoded.params().state_model, StateModel::Account);
+        assert_eq!(decoded.balance("bob"), 4);
+    }
+
+    #[test]
+    fn transactions_are_proven_against_the_merkle_root() {
+        let mut blockchain = Blockchain::new(Block::genesis(data("Genesis"), 0, 0, &Blake3), Algorithm::Blake3).unwrap();
+        let transactions: Vec<Transaction> = (0..5).map(|n| Transaction::data(&n.to_string())).collect();
+        blockchain.add_transactions(transactions.clone()).unwrap();
+
+        let root = *blockchain.latest().merkle_root();
+        for tx in &transactions {
+            let proof = blockchain.prove_transaction(&tx.txid()).unwrap();
+            assert!(proof.verify(&Blake3, &tx.txid(), &root));
+            assert!(!proof.verify(&Sha256, &tx.txid(), &root));
+        }
+        assert!(blockchain.prove_transaction(&Transaction::data("absent").txid()).is_none());
+    }
+
+    #[test]
+    fn open_reloads_stored_chain() {
+        let path = env::temp_dir().join(format!("blockchain-open-{}.log", process::id()));
+        let _ = fs::remove_file(&path);
+        let spec = GenesisSpec::default();
+
+        let tip = {
+            let mut blockchain = Blockchain::open(&path, &spec).unwrap();
+            blockchain.add_block("shared").unwrap();
+            blockchain.add_block("local").unwrap();
+
+            // a heavier branch replaces the last block in the log
+            let shared = blockchain.blocks[1].clone();
+            let r2 = child(&shared, "r2");
+            let r3 = child(&r2, "r3");
+            blockchain.extend_from(shared.hash(), vec![r2, r3]).unwrap();
+            *blockchain.latest().hash()
+        };
+
+        let blockchain = Blockchain::open(&path, &spec).unwrap();
+        assert_eq!(blockchain.len(), 4);
+        assert_eq!(*blockchain.latest().hash(), tip);
+        assert_eq!(blockchain.get(2).unwrap().payload(), "r2");
+
+        let other = GenesisSpec { payload: "Other genesis".to_string(), ..GenesisSpec::default() };
+        assert!(matches!(Blockchain::open(&path, &other), Err(Error::GenesisMismatch)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn binary_encoding_round_trips() {
+        let mut blockchain = Blockchain::new(Block::genesis(data("Genesis"), 0, 0, &Blake3), Algorithm::Blake3).unwrap();
+        blockchain.add_block("one").unwrap();
+        blockchain.add_block("two").unwrap();
+
+        let block = blockchain.latest();
+        let decoded = Block::from_bytes(&block.to_bytes()).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(decoded.payload(), "two");
+
+        let decoded = Blockchain::from_bytes(&blockchain.to_bytes()).unwrap();
+        assert_eq!(decoded.params(), blockchain.params());
+        assert_eq!(decoded.latest().hash(), blockchain.latest().hash());
+        assert_eq!(decoded.len(), 3);
+    }
+
+    #[test]
+    fn decoding_rejects_damaged_input() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.add_block("one").unwrap();
+
+        let bytes = blockchain.latest().to_bytes();
+        assert!(matches!(Block::from_bytes(&bytes[..bytes.len() - 1]), Err(Error::Decode(_))));
+
+        // chains are only read in the format they are written in
+        let mut bytes = blockchain.to_bytes();
+        assert!(matches!(Blockchain::from_bytes(&bytes[8..]), Err(Error::Decode(_))));
+        bytes[8..12].copy_from_slice(&(CHAIN_FORMAT_VERSION + 1).to_be_bytes());
+        assert!(matches!(Blockchain::from_bytes(&bytes), Err(Error::Decode(_))));
+
+        // a forged payload decodes but fails validation
+        blockchain.blocks[1].transactions[0].data = "forged".to_string();
+        assert!(matches!(Blockchain::from_bytes(&blockchain.to_bytes()), Err(Error::MerkleMismatch { index: 1 })));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trips() {
+        let mut blockchain = Blockchain::new(genesis(), Algorithm::Sha256).unwrap();
+        blockchain.add_block("one").unwrap();
+
+        let json = serde_json::to_string(&blockchain).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["params"]["algorithm"], "sha256");
+        assert_eq!(value["blocks"][1]["hash"], blockchain.latest().hash().to_string());
+
+        let decoded: Blockchain = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.latest().hash(), blockchain.latest().hash());
+
+        let forged = json.replace("\"one\"", "\"forged\"");
+        assert!(serde_json::from_str::<Blockchain>(&forged).is_err());
+
+        // blocks of another header version are refused on the way in
+        let block = serde_json::to_string(blockchain.latest()).unwrap();
+        assert!(serde_json::from_str::<Block>(&block).is_ok());
+        let unknown = block.replace("\"version\":1", "\"version\":2");
+        assert!(serde_json::from_str::<Block>(&unknown).unwrap_err().to_string().contains("unsupported block version 2"));
+    }
+}
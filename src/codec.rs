@@ -0,0 +1,167 @@
+use chain::Error;
+use hash::Hash;
+
+/// Builds the canonical binary encoding used for hashing.
+///
+/// Integers are written big-endian at their full width and variable length
+/// fields are prefixed with their length as a `u64`, so no two different
+/// sequences of fields can produce the same bytes.
+#[derive(Debug,Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Encoder {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Encoder {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn hash(&mut self, hash: &Hash) -> &mut Encoder {
+        self.buf.extend_from_slice(hash.as_bytes());
+        self
+    }
+
+    /// Writes `bytes` preceded by their length.
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Encoder {
+        self.u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        ::std::mem::take(&mut self.buf)
+    }
+}
+
+/// Writes `bytes` as lowercase hex digits.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads bytes written as hex digits.
+pub fn from_hex(s: &str) -> Result<Vec<u8>, Error> {
+    if !s.len().is_multiple_of(2) {
+        return Err(Error::Decode("odd number of hex digits".to_string()));
+    }
+
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            ::std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| Error::Decode(format!("invalid hex digits '{}'", String::from_utf8_lossy(pair))))
+        })
+        .collect()
+}
+
+/// Reads fields written by an `Encoder`, in the same order.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder { buf }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < len {
+            return Err(Error::Decode("unexpected end of input".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn hash(&mut self) -> Result<Hash, Error> {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(self.take(32)?);
+        Ok(Hash::from_bytes(bytes))
+    }
+
+    /// Reads a length-prefixed run of bytes.
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u64()?;
+        if len > self.buf.len() as u64 {
+            return Err(Error::Decode("length exceeds remaining input".to_string()));
+        }
+        self.take(len as usize)
+    }
+
+    /// Reads a length-prefixed UTF-8 string.
+    pub fn string(&mut self) -> Result<String, Error> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Decode("invalid UTF-8".to_string()))
+    }
+
+    /// Number of bytes not read yet.
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Checks that every byte of the input was read.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Decode(format!("{} trailing bytes", self.buf.len())))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_what_was_encoded() {
+        let hash = Hash::from_bytes([7; 32]);
+        let bytes = Encoder::new().u32(1).u64(2).hash(&hash).bytes(b"three").finish();
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.u32().unwrap(), 1);
+        assert_eq!(decoder.u64().unwrap(), 2);
+        assert_eq!(decoder.hash().unwrap(), hash);
+        assert_eq!(decoder.string().unwrap(), "three");
+        assert!(decoder.finish().is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_input() {
+        let bytes = Encoder::new().bytes(b"three").finish();
+        assert!(Decoder::new(&bytes[..bytes.len() - 1]).bytes().is_err());
+
+        let bytes = Encoder::new().u64(u64::MAX).finish();
+        assert!(Decoder::new(&bytes).bytes().is_err());
+
+        let bytes = Encoder::new().u32(1).u32(2).finish();
+        let mut decoder = Decoder::new(&bytes);
+        decoder.u32().unwrap();
+        assert!(decoder.finish().is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use chain::{Block, Error, Params, MAX_DIFFICULTY};
+use hash::Algorithm;
+use issuance::Issuance;
+use state::{State, StateModel};
+use transaction::{Output, Transaction};
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Funds credited to an address when a chain starts.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: u64,
+}
+
+/// Parameters describing the first block of a chain.
+///
+/// Specs can be loaded from a plain text file with one `key = value` pair
+/// per line. Blank lines and lines starting with `#` are ignored:
+///
+/// ```text
+/// payload = Genesis block baby!
+/// timestamp = 0
+/// difficulty = 0
+/// hasher = sha256
+/// block_time = 10000
+/// retarget_window = 10
+/// state = utxo
+/// issuance = halving 50 1000
+/// alloc = alice 100
+/// alloc = bob 50
+/// ```
+#[derive(Debug,Clone,PartialEq)]
+pub struct GenesisSpec {
+    pub payload: String,
+    /// Creation time in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Starting difficulty, in leading zero bits of the block hash.
+    pub difficulty: u32,
+    /// Hash function used for every block of the chain.
+    pub algorithm: Algorithm,
+    /// Intended time between blocks, in milliseconds.
+    pub block_time: u64,
+    /// Number of blocks between difficulty adjustments.
+    pub retarget_window: u32,
+    /// Whether coins are kept in unspent outputs or account balances.
+    pub state_model: StateModel,
+    /// New coins each block's coinbase may create.
+    pub issuance: Issuance,
+    pub allocations: Vec<Allocation>,
+}
+
+impl Default for GenesisSpec {
+    fn default() -> GenesisSpec {
+        GenesisSpec {
+            payload: "Genesis block baby!".to_string(),
+            timestamp: 0,
+            difficulty: 0,
+            algorithm: Algorithm::Sha256,
+            block_time: Params::default().block_time,
+            retarget_window: Params::default().retarget_window,
+            state_model: StateModel::default(),
+            issuance: Issuance::default(),
+            allocations: Vec::new(),
+        }
+    }
+}
+
+impl GenesisSpec {
+    /// Reads a spec from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GenesisSpec, Error> {
+        let contents = fs::read_to_string(path)?;
+        contents.parse()
+    }
+
+    /// Builds the genesis block described by this spec, carrying one
+    /// transaction with the payload as its data and an output for each
+    /// allocation, and committing to the resulting state. Fails with
+    /// `Error::InvalidGenesis` if the allocations cannot all be credited.
+    pub fn block(&self) -> Result<Block, Error> {
+        let outputs = self.allocations
+            .iter()
+            .map(|allocation| Output { recipient: allocation.address.clone(), amount: allocation.amount })
+            .collect();
+        let tx = Transaction {
+            outputs,
+            ..Transaction::data(&self.payload)
+        };
+        let block = Block::genesis(vec![tx], self.timestamp, self.difficulty, &self.algorithm);
+        let root = State::new(self.state_model)
+            .root_after(&self.algorithm, &block)
+            .map_err(|_| Error::InvalidGenesis)?;
+        Ok(block.with_state_root(root, &self.algorithm))
+    }
+
+    /// Consensus rules for chains started from this spec.
+    pub fn params(&self) -> Params {
+        Params {
+            algorithm: self.algorithm,
+            block_time: self.block_time,
+            retarget_window: self.retarget_window,
+            state_model: self.state_model,
+            issuance: self.issuance,
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(line: usize, key: &str, value: &str) -> Result<T, Error> {
+    value.parse().map_err(|_| Error::InvalidSpec(line, format!("invalid {} '{}'", key, value)))
+}
+
+impl FromStr for GenesisSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<GenesisSpec, Error> {
+        let mut spec = GenesisSpec::default();
+        let mut allocated: u64 = 0;
+
+        for (number, line) in s.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+
+            // skip blank lines and comments
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(Error::InvalidSpec(number, "expected 'key = value'".to_string())),
+            };
+
+            match key {
+                "payload" => spec.payload = value.to_string(),
+                "timestamp" => spec.timestamp = parse_number(number, key, value)?,
+                "difficulty" => {
+                    spec.difficulty = parse_number(number, key, value)?;
+                    if spec.difficulty > MAX_DIFFICULTY {
+                        return Err(Error::InvalidSpec(number, format!("difficulty {} is above the maximum of {}", value, MAX_DIFFICULTY)));
+                    }
+                }
+                "block_time" => spec.block_time = parse_number(number, key, value)?,
+                "retarget_window" => spec.retarget_window = parse_number(number, key, value)?,
+                "hasher" => spec.algorithm = value.parse().map_err(|reason| Error::InvalidSpec(number, reason))?,
+                "state" => spec.state_model = value.parse().map_err(|reason| Error::InvalidSpec(number, reason))?,
+                "issuance" => spec.issuance = value.parse().map_err(|reason| Error::InvalidSpec(number, reason))?,
+                "alloc" => {
+                    let mut fields = value.split_whitespace();
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some(address), Some(amount), None) => {
+                            let amount = parse_number(number, "amount", amount)?;
+                            allocated = allocated
+                                .checked_add(amount)
+                                .ok_or_else(|| Error::InvalidSpec(number, "allocations add up to more coins than can be counted".to_string()))?;
+                            spec.allocations.push(Allocation { address: address.to_string(), amount });
+                        }
+                        _ => return Err(Error::InvalidSpec(number, "expected 'alloc = <address> <amount>'".to_string())),
+                    }
+                }
+                _ => return Err(Error::InvalidSpec(number, format!("unknown key '{}'", key))),
+            }
+        }
+
+        Ok(spec)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::Blockchain;
+    use store::MemoryStore;
+
+    #[test]
+    fn parses_all_keys() {
+        let spec: GenesisSpec = "
+            # test chain
+            payload = Hello genesis
+            timestamp = 42
+            difficulty = 3
+            hasher = blake3
+            block_time = 5000
+            retarget_window = 20
+            state = account
+            issuance = tail 50 1000 5
+            alloc = alice 100
+            alloc = bob 50
+        ".parse().unwrap();
+
+        assert_eq!(spec.payload, "Hello genesis");
+        assert_eq!(spec.timestamp, 42);
+        assert_eq!(spec.difficulty, 3);
+        assert_eq!(spec.algorithm, Algorithm::Blake3);
+        assert_eq!(spec.params(), Params {
+            algorithm: Algorithm::Blake3,
+            block_time: 5000,
+            retarget_window: 20,
+            state_model: StateModel::Account,
+            issuance: Issuance::Tail { reward: 50, interval: 1000, tail: 5 },
+        });
+        assert_eq!(spec.allocations, vec![
+            Allocation { address: "alice".to_string(), amount: 100 },
+            Allocation { address: "bob".to_string(), amount: 50 },
+        ]);
+
+        let block = spec.block().unwrap();
+        assert_eq!(block.index(), 0);
+        assert_eq!(block.timestamp(), 42);
+        let tx = &block.transactions()[0];
+        assert_eq!(tx.data, "Hello genesis");
+        assert_eq!(tx.outputs[1], Output { recipient: "bob".to_string(), amount: 50 });
+        assert_eq!(block.difficulty(), 3);
+        assert!(!block.state_root().is_zero());
+        assert!(GenesisSpec::default().block().unwrap().state_root().is_zero());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        match "timestamp = soon".parse::<GenesisSpec>() {
+            Err(Error::InvalidSpec(1, _)) => {}
+            other => panic!("expected InvalidSpec, got {:?}", other),
+        }
+        match "\nalloc = alice".parse::<GenesisSpec>() {
+            Err(Error::InvalidSpec(2, _)) => {}
+            other => panic!("expected InvalidSpec, got {:?}", other),
+        }
+        assert!("colour = blue".parse::<GenesisSpec>().is_err());
+        assert!("hasher = md5".parse::<GenesisSpec>().is_err());
+        assert!("state = ledger".parse::<GenesisSpec>().is_err());
+        assert!("difficulty = 256".parse::<GenesisSpec>().is_ok());
+        assert!(matches!("difficulty = 257".parse::<GenesisSpec>(), Err(Error::InvalidSpec(1, _))));
+
+        match "alloc = a 18446744073709551615\nalloc = a 1".parse::<GenesisSpec>() {
+            Err(Error::InvalidSpec(2, _)) => {}
+            other => panic!("expected InvalidSpec, got {:?}", other),
+        }
+
+        // nor does a chain start from such a spec built in code
+        let allocations = vec![Allocation { address: "a".to_string(), amount: u64::MAX }, Allocation { address: "a".to_string(), amount: 1 }];
+        let spec = GenesisSpec { allocations, ..GenesisSpec::default() };
+        assert!(matches!(Blockchain::from_spec(&spec), Err(Error::InvalidGenesis)));
+        assert!(matches!(Blockchain::with_store(MemoryStore::new(), &spec), Err(Error::InvalidGenesis)));
+
+        // account chains cannot even credit such allocations
+        let spec = GenesisSpec { state_model: StateModel::Account, ..spec };
+        assert!(matches!(spec.block(), Err(Error::InvalidGenesis)));
+        assert!(matches!(Blockchain::from_spec(&spec), Err(Error::InvalidGenesis)));
+    }
+}
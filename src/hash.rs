@@ -0,0 +1,265 @@
+use blake3;
+use chain::Error;
+use codec;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{self, Digest};
+use sha3;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A 32 byte digest identifying a block.
+#[derive(Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord,Default)]
+pub struct Hash([u8; 32]);
+
+impl Hash {
+    /// The all-zero hash, used as the parent of the genesis block.
+    pub fn zero() -> Hash {
+        Hash([0; 32])
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Hash {
+        Hash(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0)
+    }
+
+    /// Number of leading zero bits, read most significant byte first.
+    pub fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for byte in self.0.iter() {
+            zeros += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+
+    /// Whether the hash has at least `difficulty` leading zero bits.
+    pub fn meets_difficulty(&self, difficulty: u32) -> bool {
+        self.leading_zeros() >= difficulty
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = Error;
+
+    /// Parses 64 hex digits.
+    fn from_str(s: &str) -> Result<Hash, Error> {
+        if s.len() != 64 {
+            return Err(Error::Decode(format!("expected 64 hex digits, found {}", s.len())));
+        }
+
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&codec::from_hex(s)?);
+        Ok(Hash(bytes))
+    }
+}
+
+// hex strings in human readable formats, raw bytes otherwise
+#[cfg(feature = "serde")]
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        struct HashVisitor;
+
+        impl<'de> de::Visitor<'de> for HashVisitor {
+            type Value = Hash;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a 32 byte hash as 64 hex digits or raw bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Hash, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Hash, E> {
+                if value.len() != 32 {
+                    return Err(E::invalid_length(value.len(), &self));
+                }
+                let mut bytes = [0; 32];
+                bytes.copy_from_slice(value);
+                Ok(Hash(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashVisitor)
+        } else {
+            deserializer.deserialize_bytes(HashVisitor)
+        }
+    }
+}
+
+/// A hash function used to derive block hashes.
+pub trait BlockHasher {
+    fn digest(&self, data: &[u8]) -> Hash;
+}
+
+/// SHA-256.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Sha256;
+
+impl BlockHasher for Sha256 {
+    fn digest(&self, data: &[u8]) -> Hash {
+        Hash(sha2::Sha256::digest(data).into())
+    }
+}
+
+/// SHA-256 applied twice, as used by Bitcoin.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct DoubleSha256;
+
+impl BlockHasher for DoubleSha256 {
+    fn digest(&self, data: &[u8]) -> Hash {
+        Hash(sha2::Sha256::digest(sha2::Sha256::digest(data)).into())
+    }
+}
+
+/// SHA3-256.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Sha3_256;
+
+impl BlockHasher for Sha3_256 {
+    fn digest(&self, data: &[u8]) -> Hash {
+        Hash(sha3::Sha3_256::digest(data).into())
+    }
+}
+
+/// BLAKE3 with a 32 byte output.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Blake3;
+
+impl BlockHasher for Blake3 {
+    fn digest(&self, data: &[u8]) -> Hash {
+        Hash(*blake3::hash(data).as_bytes())
+    }
+}
+
+/// The hash function a chain is configured to use.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum Algorithm {
+    #[default]
+    Sha256,
+    DoubleSha256,
+    Sha3_256,
+    Blake3,
+}
+
+impl BlockHasher for Algorithm {
+    fn digest(&self, data: &[u8]) -> Hash {
+        match *self {
+            Algorithm::Sha256 => Sha256.digest(data),
+            Algorithm::DoubleSha256 => DoubleSha256.digest(data),
+            Algorithm::Sha3_256 => Sha3_256.digest(data),
+            Algorithm::Blake3 => Blake3.digest(data),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::DoubleSha256 => "double-sha256",
+            Algorithm::Sha3_256 => "sha3-256",
+            Algorithm::Blake3 => "blake3",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Algorithm, String> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            "double-sha256" => Ok(Algorithm::DoubleSha256),
+            "sha3-256" => Ok(Algorithm::Sha3_256),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => Err(format!("unknown hash algorithm '{}'", s)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_as_str!(Algorithm);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithms_match_known_digests() {
+        let vectors = [
+            (Algorithm::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (Algorithm::DoubleSha256, "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"),
+            (Algorithm::Sha3_256, "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+            (Algorithm::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+
+        for &(algorithm, expected) in vectors.iter() {
+            assert_eq!(algorithm.digest(b"abc").to_string(), expected, "{}", algorithm);
+            assert_eq!(algorithm.to_string().parse::<Algorithm>(), Ok(algorithm));
+        }
+    }
+
+    #[test]
+    fn counts_leading_zero_bits() {
+        let mut bytes = [0xff; 32];
+        assert_eq!(Hash::from_bytes(bytes).leading_zeros(), 0);
+
+        bytes[0] = 0;
+        bytes[1] = 0x1f;
+        let hash = Hash::from_bytes(bytes);
+        assert_eq!(hash.leading_zeros(), 11);
+        assert!(hash.meets_difficulty(11));
+        assert!(!hash.meets_difficulty(12));
+
+        assert_eq!(Hash::zero().leading_zeros(), 256);
+    }
+
+    #[test]
+    fn parses_hex() {
+        let hash = Sha256.digest(b"abc");
+        assert_eq!(hash.to_string().parse::<Hash>().unwrap(), hash);
+        assert!("abc".parse::<Hash>().is_err());
+        assert!("zz".repeat(32).parse::<Hash>().is_err());
+    }
+}
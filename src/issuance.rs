@@ -0,0 +1,114 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How many new coins the coinbase of each block may create.
+///
+/// Schedules are written as text as their name followed by their numbers,
+/// e.g. `fixed 50`, `halving 50 1000` or `tail 50 1000 5`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Issuance {
+    /// Every block creates `reward` coins.
+    Fixed { reward: u64 },
+    /// Blocks create `reward` coins, halved every `interval` blocks until
+    /// nothing is left.
+    Halving { reward: u64, interval: u32 },
+    /// Like `Halving`, but the reward never drops below `tail`, so coins are
+    /// created forever.
+    Tail { reward: u64, interval: u32, tail: u64 },
+}
+
+impl Default for Issuance {
+    fn default() -> Issuance {
+        Issuance::Fixed { reward: 0 }
+    }
+}
+
+// `reward` halved once for every `interval` blocks before `height`
+fn halved(reward: u64, interval: u32, height: u32) -> u64 {
+    let halvings = height.checked_div(interval).unwrap_or(0);
+    reward.checked_shr(halvings).unwrap_or(0)
+}
+
+impl Issuance {
+    /// New coins the coinbase of the block at `height` may create on top of
+    /// the fees it collects. The genesis block creates its coins through
+    /// its allocations instead.
+    pub fn reward(&self, height: u32) -> u64 {
+        if height == 0 {
+            return 0;
+        }
+
+        match *self {
+            Issuance::Fixed { reward } => reward,
+            Issuance::Halving { reward, interval } => halved(reward, interval, height),
+            Issuance::Tail { reward, interval, tail } => halved(reward, interval, height).max(tail),
+        }
+    }
+}
+
+impl fmt::Display for Issuance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issuance::Fixed { reward } => write!(f, "fixed {}", reward),
+            Issuance::Halving { reward, interval } => write!(f, "halving {} {}", reward, interval),
+            Issuance::Tail { reward, interval, tail } => write!(f, "tail {} {} {}", reward, interval, tail),
+        }
+    }
+}
+
+impl FromStr for Issuance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Issuance, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let number = |field: &str| field.parse().map_err(|_| format!("invalid issuance number '{}'", field));
+        let interval = |field: &str| match number(field)? {
+            0 => Err("issuance interval must be at least one block".to_string()),
+            interval if interval > u64::from(u32::MAX) => Err(format!("issuance interval {} is too long", interval)),
+            interval => Ok(interval as u32),
+        };
+
+        match fields.as_slice() {
+            ["fixed", reward] => Ok(Issuance::Fixed { reward: number(reward)? }),
+            ["halving", reward, every] => Ok(Issuance::Halving { reward: number(reward)?, interval: interval(every)? }),
+            ["tail", reward, every, tail] => Ok(Issuance::Tail { reward: number(reward)?, interval: interval(every)?, tail: number(tail)? }),
+            _ => Err(format!(
+                "unknown issuance '{}', expected 'fixed <reward>', 'halving <reward> <interval>' or 'tail <reward> <interval> <tail>'",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_as_str!(Issuance);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_pay_out_by_height() {
+        let fixed = Issuance::Fixed { reward: 50 };
+        assert_eq!((fixed.reward(0), fixed.reward(1), fixed.reward(1_000_000)), (0, 50, 50));
+
+        let halving = Issuance::Halving { reward: 50, interval: 10 };
+        let rewards: Vec<u64> = [1, 9, 10, 25, 59, 60, 1_000].iter().map(|&height| halving.reward(height)).collect();
+        assert_eq!(rewards, vec![50, 50, 25, 12, 1, 0, 0]);
+
+        let tail = Issuance::Tail { reward: 50, interval: 10, tail: 3 };
+        assert_eq!((tail.reward(10), tail.reward(40), tail.reward(u32::MAX)), (25, 3, 3));
+    }
+
+    #[test]
+    fn schedules_round_trip_as_text() {
+        for issuance in &[Issuance::default(), Issuance::Halving { reward: 50, interval: 10 }, Issuance::Tail { reward: 8, interval: 2, tail: 1 }] {
+            assert_eq!(issuance.to_string().parse::<Issuance>(), Ok(*issuance));
+        }
+
+        assert!("halving 50 0".parse::<Issuance>().is_err());
+        assert!("fixed fifty".parse::<Issuance>().is_err());
+        assert!("minted 50".parse::<Issuance>().is_err());
+    }
+}
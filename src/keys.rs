@@ -0,0 +1,384 @@
+use bs58;
+use chain::Error;
+use codec::{self, Decoder, Encoder};
+use ed25519_dalek;
+use hash::{BlockHasher, Sha256};
+use k256::ecdsa as secp256k1;
+use k256::ecdsa::signature::{Signer, Verifier};
+use rand::rngs::OsRng;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Signature scheme a key belongs to.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Default)]
+pub enum Scheme {
+    #[default]
+    Ed25519,
+    /// ECDSA over secp256k1, signing the SHA-256 hash of the message.
+    Secp256k1,
+}
+
+impl Scheme {
+    fn id(self) -> u32 {
+        match self {
+            Scheme::Ed25519 => 0,
+            Scheme::Secp256k1 => 1,
+        }
+    }
+
+    fn from_id(id: u32) -> Result<Scheme, Error> {
+        match id {
+            0 => Ok(Scheme::Ed25519),
+            1 => Ok(Scheme::Secp256k1),
+            id => Err(Error::Decode(format!("unknown signature scheme {}", id))),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Scheme::Ed25519 => "ed25519",
+            Scheme::Secp256k1 => "secp256k1",
+        })
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scheme, String> {
+        match s {
+            "ed25519" => Ok(Scheme::Ed25519),
+            "secp256k1" => Ok(Scheme::Secp256k1),
+            _ => Err(format!("unknown signature scheme '{}'", s)),
+        }
+    }
+}
+
+/// Version byte that starts the encoding of every address.
+pub const ADDRESS_VERSION: u8 = 0;
+
+/// Checks that `address` is a Base58Check address with the current
+/// version byte and a 20 byte key hash.
+pub fn check_address(address: &str) -> Result<(), Error> {
+    let payload = bs58::decode(address)
+        .with_check(Some(ADDRESS_VERSION))
+        .into_vec()
+        .map_err(|_| Error::InvalidAddress(address.to_string()))?;
+
+    if payload.len() != 21 {
+        return Err(Error::InvalidAddress(address.to_string()));
+    }
+    Ok(())
+}
+
+// splits text of the form `<scheme>:<hex>`
+fn parse_tagged(s: &str) -> Result<(Scheme, Vec<u8>), Error> {
+    let mut parts = s.splitn(2, ':');
+    let scheme = parts.next().unwrap_or("").parse().map_err(Error::Decode)?;
+    let hex = parts.next().ok_or_else(|| Error::Decode("expected '<scheme>:<hex>'".to_string()))?;
+    Ok((scheme, codec::from_hex(hex)?))
+}
+
+/// The public half of a keypair, which checks signatures.
+///
+/// Ed25519 keys are 32 bytes and secp256k1 keys are 33 byte compressed
+/// points. As text a key is written as its scheme and hex bytes, such as
+/// `ed25519:3b6a27bc...`.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct PublicKey {
+    scheme: Scheme,
+    bytes: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Reads a `scheme` key, checking that it is a valid point.
+    pub fn from_bytes(scheme: Scheme, bytes: &[u8]) -> Result<PublicKey, Error> {
+        let valid = match scheme {
+            Scheme::Ed25519 => ed25519_key(bytes).is_some(),
+            Scheme::Secp256k1 => secp256k1::VerifyingKey::from_sec1_bytes(bytes).is_ok(),
+        };
+        if !valid {
+            return Err(Error::Decode(format!("invalid {} public key", scheme)));
+        }
+
+        Ok(PublicKey { scheme, bytes: bytes.to_vec() })
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The address coins are paid to for this key.
+    ///
+    /// The first 20 bytes of the SHA-256 hash of the scheme and key follow
+    /// `ADDRESS_VERSION`, and are written in Base58Check so that a mistyped
+    /// address fails its checksum rather than sending coins nowhere.
+    pub fn address(&self) -> String {
+        let encoded = Encoder::new().u32(self.scheme.id()).bytes(&self.bytes).finish();
+        let mut payload = vec![ADDRESS_VERSION];
+        payload.extend_from_slice(&Sha256.digest(&encoded).as_bytes()[..20]);
+        bs58::encode(payload).with_check().into_string()
+    }
+
+    /// Whether `signature` was made over `message` by this key's keypair.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        if signature.scheme != self.scheme {
+            return false;
+        }
+
+        match self.scheme {
+            Scheme::Ed25519 => {
+                let key = ed25519_key(&self.bytes);
+                let signature = ed25519_dalek::Signature::from_slice(&signature.bytes).ok();
+                match (key, signature) {
+                    (Some(key), Some(signature)) => key.verify_strict(message, &signature).is_ok(),
+                    _ => false,
+                }
+            }
+            Scheme::Secp256k1 => {
+                // high-S signatures are rejected, so a signature has one valid form
+                let key = secp256k1::VerifyingKey::from_sec1_bytes(&self.bytes).ok();
+                let signature = secp256k1::Signature::from_slice(&signature.bytes).ok();
+                match (key, signature) {
+                    (Some(key), Some(signature)) => key.verify(message, &signature).is_ok(),
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.scheme.id()).bytes(&self.bytes);
+    }
+
+    pub(crate) fn decode(decoder: &mut Decoder) -> Result<PublicKey, Error> {
+        let scheme = Scheme::from_id(decoder.u32()?)?;
+        PublicKey::from_bytes(scheme, decoder.bytes()?)
+    }
+}
+
+fn ed25519_key(bytes: &[u8]) -> Option<ed25519_dalek::VerifyingKey> {
+    let mut key = [0; 32];
+    if bytes.len() != key.len() {
+        return None;
+    }
+    key.copy_from_slice(bytes);
+    ed25519_dalek::VerifyingKey::from_bytes(&key).ok()
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.scheme, codec::to_hex(&self.bytes))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PublicKey, Error> {
+        let (scheme, bytes) = parse_tagged(s)?;
+        PublicKey::from_bytes(scheme, &bytes)
+    }
+}
+
+/// A 64 byte signature made by a keypair of either scheme.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Signature {
+    scheme: Scheme,
+    bytes: Vec<u8>,
+}
+
+impl Signature {
+    pub fn from_bytes(scheme: Scheme, bytes: &[u8]) -> Result<Signature, Error> {
+        if bytes.len() != 64 {
+            return Err(Error::Decode(format!("expected a 64 byte signature, found {} bytes", bytes.len())));
+        }
+        Ok(Signature { scheme, bytes: bytes.to_vec() })
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(self.scheme.id()).bytes(&self.bytes);
+    }
+
+    pub(crate) fn decode(decoder: &mut Decoder) -> Result<Signature, Error> {
+        let scheme = Scheme::from_id(decoder.u32()?)?;
+        Signature::from_bytes(scheme, decoder.bytes()?)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.scheme, codec::to_hex(&self.bytes))
+    }
+}
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Signature, Error> {
+        let (scheme, bytes) = parse_tagged(s)?;
+        Signature::from_bytes(scheme, &bytes)
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_as_str!(PublicKey);
+#[cfg(feature = "serde")]
+serde_as_str!(Signature);
+
+#[derive(Clone)]
+enum Secret {
+    Ed25519(ed25519_dalek::SigningKey),
+    Secp256k1(secp256k1::SigningKey),
+}
+
+/// A secret key along with its public key.
+#[derive(Clone)]
+pub struct Keypair {
+    secret: Secret,
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // never print the secret key
+        f.debug_struct("Keypair").field("public_key", &self.public_key()).finish()
+    }
+}
+
+impl Keypair {
+    /// Creates a new random keypair from the operating system's random
+    /// number generator.
+    pub fn generate(scheme: Scheme) -> Keypair {
+        let secret = match scheme {
+            Scheme::Ed25519 => Secret::Ed25519(ed25519_dalek::SigningKey::generate(&mut OsRng)),
+            Scheme::Secp256k1 => Secret::Secp256k1(secp256k1::SigningKey::random(&mut OsRng)),
+        };
+        Keypair { secret }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match self.secret {
+            Secret::Ed25519(_) => Scheme::Ed25519,
+            Secret::Secp256k1(_) => Scheme::Secp256k1,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let bytes = match self.secret {
+            Secret::Ed25519(ref key) => key.verifying_key().to_bytes().to_vec(),
+            Secret::Secp256k1(ref key) => key.verifying_key().to_sec1_bytes().to_vec(),
+        };
+        PublicKey { scheme: self.scheme(), bytes }
+    }
+
+    /// Signs `message`.
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        let bytes = match self.secret {
+            Secret::Ed25519(ref key) => key.sign(message).to_bytes().to_vec(),
+            Secret::Secp256k1(ref key) => {
+                let signature: secp256k1::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+        };
+        Signature { scheme: self.scheme(), bytes }
+    }
+
+    /// Encodes the scheme and the 32 byte secret key.
+    ///
+    /// Anyone holding these bytes can sign as this keypair, so they should
+    /// not be stored unencrypted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let secret = match self.secret {
+            Secret::Ed25519(ref key) => key.to_bytes().to_vec(),
+            Secret::Secp256k1(ref key) => key.to_bytes().to_vec(),
+        };
+        Encoder::new().u32(self.scheme().id()).bytes(&secret).finish()
+    }
+
+    /// Loads a keypair written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Keypair, Error> {
+        let mut decoder = Decoder::new(bytes);
+        let scheme = Scheme::from_id(decoder.u32()?)?;
+        let secret = decoder.bytes()?;
+        decoder.finish()?;
+
+        let invalid = || Error::Decode(format!("invalid {} secret key", scheme));
+        let secret = match scheme {
+            Scheme::Ed25519 => {
+                let mut key = [0; 32];
+                if secret.len() != key.len() {
+                    return Err(invalid());
+                }
+                key.copy_from_slice(secret);
+                Secret::Ed25519(ed25519_dalek::SigningKey::from_bytes(&key))
+            }
+            Scheme::Secp256k1 => Secret::Secp256k1(secp256k1::SigningKey::from_slice(secret).map_err(|_| invalid())?),
+        };
+        Ok(Keypair { secret })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keypairs_sign_and_verify() {
+        for &scheme in &[Scheme::Ed25519, Scheme::Secp256k1] {
+            let keypair = Keypair::generate(scheme);
+            let public_key = keypair.public_key();
+            let signature = keypair.sign(b"message");
+
+            assert!(public_key.verify(b"message", &signature), "{}", scheme);
+            assert!(!public_key.verify(b"other message", &signature), "{}", scheme);
+            assert!(!Keypair::generate(scheme).public_key().verify(b"message", &signature), "{}", scheme);
+        }
+
+        // a signature from one scheme never passes for the other
+        let signature = Keypair::generate(Scheme::Ed25519).sign(b"message");
+        assert!(!Keypair::generate(Scheme::Secp256k1).public_key().verify(b"message", &signature));
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for &scheme in &[Scheme::Ed25519, Scheme::Secp256k1] {
+            let keypair = Keypair::generate(scheme);
+            let loaded = Keypair::from_bytes(&keypair.to_bytes()).unwrap();
+            assert_eq!(loaded.public_key(), keypair.public_key());
+
+            let public_key = keypair.public_key();
+            assert_eq!(public_key.to_string().parse::<PublicKey>().unwrap(), public_key);
+            assert!(check_address(&public_key.address()).is_ok());
+
+            let signature = keypair.sign(b"message");
+            assert_eq!(signature.to_string().parse::<Signature>().unwrap(), signature);
+        }
+
+        // one changed character breaks the checksum
+        let address = Keypair::generate(Scheme::Ed25519).public_key().address();
+        let last = if address.ends_with('2') { "3" } else { "2" };
+        let typo = format!("{}{}", &address[..address.len() - 1], last);
+        assert!(matches!(check_address(&typo), Err(Error::InvalidAddress(_))));
+        assert!(check_address("alice").is_err());
+
+        assert!("ed25519:abcd".parse::<PublicKey>().is_err());
+        assert!("rsa:abcd".parse::<PublicKey>().is_err());
+        assert!(Keypair::from_bytes(&[0; 4]).is_err());
+    }
+}
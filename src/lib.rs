@@ -1,6 +1,51 @@
+// quick_error! recurses once per error variant
+#![recursion_limit = "256"]
+
+extern crate blake3;
+extern crate bs58;
+extern crate chacha20poly1305;
+extern crate crc32fast;
+extern crate ed25519_dalek;
+extern crate k256;
 #[macro_use] extern crate quick_error;
+extern crate rand;
+extern crate scrypt;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+extern crate sha2;
+extern crate sha3;
+#[cfg(feature = "sled")]
+extern crate sled;
+#[macro_use] extern crate tracing;
+
+// declared first so every module below can use its macros
+#[macro_use]
+mod macros;
 
+pub mod account;
+pub mod builder;
 pub mod chain;
+pub mod clock;
+pub mod codec;
+pub mod genesis;
+pub mod hash;
+pub mod issuance;
+pub mod keys;
+pub mod mempool;
+pub mod merkle;
+pub mod miner;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+pub mod state;
+pub mod store;
+pub mod transaction;
+pub mod utxo;
+pub mod wallet;
+
+#[cfg(test)]
+mod test_utils;
 
 
 #[cfg(test)]
@@ -1,9 +1,155 @@
 extern crate blockchain;
+extern crate tracing_subscriber;
 
-use blockchain::chain;
+use blockchain::chain::{self, Blockchain, Error};
+use blockchain::genesis::GenesisSpec;
+use blockchain::keys::Scheme;
+use blockchain::wallet::Wallet;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "usage:
+    blockchain [-v|-q]
+    blockchain wallet new <keystore> [ed25519|secp256k1]
+    blockchain wallet list <keystore>
+    blockchain wallet balance <keystore> <chain> [--genesis <spec>]
+    blockchain wallet send <keystore> <chain> <from> <to> <amount> [fee] [--genesis <spec>]";
+
+fn is_log_flag(arg: &str) -> bool {
+    (arg.starts_with("-v") && arg[1..].chars().all(|c| c == 'v')) || arg == "-q"
+}
+
+// default log level, raised by each `-v` and lowered by `-q`
+fn log_level() -> &'static str {
+    let mut verbosity: i32 = 0;
+
+    for arg in env::args().skip(1) {
+        if arg == "-q" {
+            verbosity -= 1;
+        } else if is_log_flag(&arg) {
+            verbosity += arg.len() as i32 - 1;
+        }
+    }
+
+    match verbosity {
+        i32::MIN..=-1 => "error",
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    }
+}
+
+// BLOCKCHAIN_PASSPHRASE if set, otherwise a line read from stdin
+fn passphrase() -> Result<String, Error> {
+    if let Ok(passphrase) = env::var("BLOCKCHAIN_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    eprint!("passphrase: ");
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn open_chain(path: &str, genesis: Option<&str>) -> Result<Blockchain, Error> {
+    let spec = match genesis {
+        Some(genesis) => GenesisSpec::load(genesis)?,
+        None => GenesisSpec::default(),
+    };
+    Blockchain::open(path, &spec)
+}
+
+fn parse_amount(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("invalid amount {}", value))
+}
+
+fn wallet(args: &[String], genesis: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["new", keystore] | ["new", keystore, _] => {
+            let scheme: Scheme = match args.get(2) {
+                Some(scheme) => scheme.parse()?,
+                None => Scheme::default(),
+            };
+            let passphrase = passphrase()?;
+            let mut wallet = match Wallet::load(keystore, &passphrase) {
+                Ok(wallet) => wallet,
+                Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::NotFound => Wallet::new(),
+                Err(err) => return Err(err.into()),
+            };
+            let address = wallet.generate(scheme);
+            wallet.save(keystore, &passphrase)?;
+            println!("{}", address);
+        }
+        ["list", keystore] => {
+            for address in Wallet::load(keystore, &passphrase()?)?.addresses() {
+                println!("{}", address);
+            }
+        }
+        ["balance", keystore, chain] => {
+            let wallet = Wallet::load(keystore, &passphrase()?)?;
+            let blockchain = open_chain(chain, genesis)?;
+            for address in wallet.addresses() {
+                println!("{} {}", address, blockchain.balance(&address));
+            }
+            println!("total {}", wallet.balance(&blockchain));
+        }
+        ["send", keystore, chain, from, to, amount] | ["send", keystore, chain, from, to, amount, _] => {
+            let fee = match args.get(6) {
+                Some(fee) => parse_amount(fee)?,
+                None => 0,
+            };
+            let wallet = Wallet::load(keystore, &passphrase()?)?;
+            let mut blockchain = open_chain(chain, genesis)?;
+            let tx = wallet.transfer(&blockchain, from, to, parse_amount(amount)?, fee)?;
+            let txid = tx.txid();
+            blockchain.add_transactions(vec![tx])?;
+            println!("{}", txid);
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
 
 fn main() {
-    println!("I was running...");
+    // RUST_LOG takes precedence over the command line flags
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(log_level()));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args: Vec<String> = env::args().skip(1).filter(|arg| !is_log_flag(arg)).collect();
+    let genesis = match args.iter().position(|arg| arg == "--genesis") {
+        Some(position) if position + 1 < args.len() => Some(args.drain(position..position + 2).nth(1).unwrap()),
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+        None => None,
+    };
+
+    match args.first().map(String::as_str) {
+        Some("wallet") => {
+            if let Err(err) = wallet(&args[1..], genesis.as_deref()) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+        None => {
+            println!("I was running...");
 
-    chain::run();
-}
\ No newline at end of file
+            chain::run();
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use chain::{Blockchain, Error, Reorg};
+use clock::{Clock, SystemClock};
+use hash::Hash;
+use state::PendingChanges;
+use transaction::Transaction;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Default limit on the encoded size of the pooled transactions, in bytes.
+pub const DEFAULT_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// Default time a transaction may wait in the pool, in milliseconds.
+pub const DEFAULT_EXPIRY: u64 = 2 * 60 * 60 * 1000;
+
+/// A transaction waiting in a `Mempool`.
+#[derive(Debug,Clone)]
+pub struct PoolEntry {
+    pub tx: Transaction,
+    pub txid: Hash,
+    /// Length of the transaction's encoding, in bytes.
+    pub size: usize,
+    /// Time the transaction entered the pool, in milliseconds since the Unix epoch.
+    pub added: u64,
+    // position in the order the pooled transactions apply in
+    sequence: u64,
+}
+
+impl PoolEntry {
+    /// Compares the fee paid per byte with that of `other`.
+    pub fn cmp_fee_rate(&self, other: &PoolEntry) -> Ordering {
+        let fee = u128::from(self.tx.fee) * other.size as u128;
+        let other_fee = u128::from(other.tx.fee) * self.size as u128;
+        fee.cmp(&other_fee)
+    }
+}
+
+/// Transactions waiting to be mined, checked against the state after the
+/// main chain's latest block.
+///
+/// Every pooled transaction applies on top of the chain state and the
+/// transactions that entered the pool before it, so a transaction may spend
+/// the outputs of another pooled transaction or carry the nonce after it.
+/// The pool is bounded in size, dropping the transactions that pay the
+/// least per byte first, and drops transactions that wait too long.
+///
+/// The pool follows the chain: transactions a new block includes, or that
+/// no longer apply once it is added, are dropped by `sync`, and
+/// `reorganized` puts back the transactions of disconnected blocks.
+pub struct Mempool {
+    entries: HashMap<Hash, PoolEntry>,
+    // what the pooled transactions change, on top of the state at `tip`
+    changes: Option<PendingChanges>,
+    tip: Hash,
+    size: usize,
+    max_size: usize,
+    expiry: u64,
+    next_sequence: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl fmt::Debug for Mempool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mempool")
+            .field("entries", &self.entries.len())
+            .field("size", &self.size)
+            .field("max_size", &self.max_size)
+            .field("expiry", &self.expiry)
+            .finish()
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Mempool {
+        Mempool::with_limits(DEFAULT_MAX_SIZE, DEFAULT_EXPIRY)
+    }
+}
+
+impl Mempool {
+    /// Creates an empty pool with the default limits.
+    pub fn new() -> Mempool {
+        Mempool::default()
+    }
+
+    /// Creates an empty pool holding up to `max_size` bytes of transactions,
+    /// each for up to `expiry` milliseconds.
+    pub fn with_limits(max_size: usize, expiry: u64) -> Mempool {
+        Mempool {
+            entries: HashMap::new(),
+            changes: None,
+            tip: Hash::zero(),
+            size: 0,
+            max_size,
+            expiry,
+            next_sequence: 0,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Uses `clock` instead of the system clock to date and expire transactions.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Number of pooled transactions.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Encoded size of the pooled transactions, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the transaction with `txid` is pooled.
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Looks up a pooled transaction.
+    pub fn get(&self, txid: &Hash) -> Option<&PoolEntry> {
+        self.entries.get(txid)
+    }
+
+    /// Pooled transactions, paying the most per byte first. Transactions
+    /// paying the same rate are listed in the order they entered the pool.
+    pub fn entries(&self) -> Vec<&PoolEntry> {
+        let mut entries: Vec<&PoolEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.cmp_fee_rate(a).then(a.sequence.cmp(&b.sequence)));
+        entries
+    }
+
+    /// Checks `tx` and adds it to the pool, returning its id.
+    ///
+    /// The transaction must follow the transaction rules, carry the
+    /// signature it needs and apply on top of the main chain of
+    /// `blockchain` and the transactions already pooled.
+    pub fn submit(&mut self, blockchain: &Blockchain, tx: Transaction) -> Result<Hash, Error> {
+        if *blockchain.latest().hash() != self.tip {
+            self.sync(blockchain);
+        }
+        self.expire(blockchain);
+
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) || blockchain.get_transaction(&txid).is_some() {
+            return Err(Error::KnownTransaction(txid));
+        }
+
+        let index = blockchain.len() as u32;
+        if let Err(reason) = tx.check() {
+            return Err(Error::InvalidTransaction { index, txid, reason });
+        }
+        if !tx.is_signed() {
+            return Err(Error::InvalidSignature { index, txid });
+        }
+        let state = blockchain.state();
+        let changes = self.changes.get_or_insert_with(|| state.pending());
+        state.apply_pending(changes, index, &tx)?;
+
+        debug!(txid = %txid, fee = tx.fee, "adding transaction to pool");
+        let entry = PoolEntry {
+            size: tx.size(),
+            tx,
+            txid,
+            added: self.clock.now(),
+            sequence: self.next_sequence,
+        };
+        self.next_sequence += 1;
+        self.size += entry.size;
+        self.entries.insert(txid, entry);
+
+        if self.size > self.max_size {
+            self.evict(blockchain);
+            if !self.entries.contains_key(&txid) {
+                return Err(Error::PoolFull(txid));
+            }
+        }
+        Ok(txid)
+    }
+
+    /// Drops the transactions that have waited longer than the expiry,
+    /// along with any that build on them.
+    pub fn expire(&mut self, blockchain: &Blockchain) {
+        let now = self.clock.now();
+        let expiry = self.expiry;
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| now.saturating_sub(entry.added) < expiry);
+
+        if self.entries.len() < before {
+            debug!(expired = before - self.entries.len(), "expiring pooled transactions");
+            self.revalidate(blockchain);
+        }
+    }
+
+    /// Catches up with the main chain of `blockchain`, dropping the
+    /// transactions it now includes and any that no longer apply on top of
+    /// it, such as ones spending coins a new block spent.
+    pub fn sync(&mut self, blockchain: &Blockchain) {
+        self.revalidate(blockchain);
+    }
+
+    /// Catches up with a reorganization of the main chain of `blockchain`,
+    /// putting back the transactions of the disconnected blocks that the new
+    /// main chain does not include. They go ahead of the pooled ones, which
+    /// may build on them.
+    pub fn reorganized(&mut self, blockchain: &Blockchain, reorg: &Reorg) {
+        let now = self.clock.now();
+        let index = blockchain.len() as u32;
+        let disconnected = reorg.disconnected.iter().rev().flat_map(|block| block.transactions().iter());
+        let mut entries: Vec<PoolEntry> = disconnected
+            .filter(|tx| tx.check().is_ok() && tx.is_signed())
+            .map(|tx| PoolEntry { size: tx.size(), tx: tx.clone(), txid: tx.txid(), added: now, sequence: 0 })
+            .filter(|entry| !self.entries.contains_key(&entry.txid))
+            .collect();
+        debug!(count = entries.len(), index, "returning transactions of disconnected blocks to pool");
+
+        let mut pooled: Vec<PoolEntry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        pooled.sort_by_key(|entry| entry.sequence);
+        entries.extend(pooled);
+        for (sequence, entry) in entries.iter_mut().enumerate() {
+            entry.sequence = sequence as u64;
+        }
+        self.next_sequence = entries.len() as u64;
+        self.entries = entries.into_iter().map(|entry| (entry.txid, entry)).collect();
+
+        self.revalidate(blockchain);
+        if self.size > self.max_size {
+            self.evict(blockchain);
+        }
+    }
+
+    // drops the transactions paying the least per byte, and the ones that
+    // build on them, until the pool fits its size limit
+    fn evict(&mut self, blockchain: &Blockchain) {
+        while self.size > self.max_size {
+            let lowest = self.entries
+                .values()
+                .min_by(|a, b| a.cmp_fee_rate(b).then(b.sequence.cmp(&a.sequence)))
+                .map(|entry| entry.txid)
+                .expect("a pool over its size limit is not empty");
+            debug!(txid = %lowest, "evicting transaction from full pool");
+            self.entries.remove(&lowest);
+            self.revalidate(blockchain);
+        }
+    }
+
+    // re-applies the pooled transactions in order on top of the main chain,
+    // dropping the ones it includes and the ones that no longer apply
+    fn revalidate(&mut self, blockchain: &Blockchain) {
+        let state = blockchain.state();
+        let index = blockchain.len() as u32;
+        let mut changes = state.pending();
+
+        let mut entries: Vec<PoolEntry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.sequence);
+        self.size = 0;
+        for entry in entries {
+            if blockchain.get_transaction(&entry.txid).is_some() {
+                continue;
+            }
+            match state.apply_pending(&mut changes, index, &entry.tx) {
+                Ok(()) => {
+                    self.size += entry.size;
+                    self.entries.insert(entry.txid, entry);
+                }
+                Err(err) => debug!(txid = %entry.txid, error = %err, "dropping transaction from pool"),
+            }
+        }
+
+        self.changes = Some(changes);
+        self.tip = *blockchain.latest().hash();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::Block;
+    use clock::MockClock;
+    use hash::Sha256;
+    use keys::{Keypair, Scheme};
+    use state::StateModel;
+    use transaction::{OutPoint, Output};
+    use test_utils::funded;
+
+    fn spend(keypair: &Keypair, blockchain: &Blockchain, index: u32, fee: u64) -> Transaction {
+        let coin = OutPoint { txid: blockchain.genesis().transactions()[0].txid(), index };
+        let mut tx = Transaction {
+            inputs: vec![coin],
+            outputs: vec![Output { recipient: "bob".to_string(), amount: 10 - fee }],
+            fee,
+            ..Transaction::default()
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    #[test]
+    fn pool_orders_by_fee_rate_and_evicts_the_cheapest() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let blockchain = funded(&keypair, &[10, 10, 10, 10], StateModel::Utxo);
+        let (low, mid, high) = (spend(&keypair, &blockchain, 0, 1), spend(&keypair, &blockchain, 1, 2), spend(&keypair, &blockchain, 2, 3));
+
+        let mut mempool = Mempool::with_limits(low.size() * 2, DEFAULT_EXPIRY);
+        mempool.submit(&blockchain, low.clone()).unwrap();
+        mempool.submit(&blockchain, high.clone()).unwrap();
+        assert!(matches!(mempool.submit(&blockchain, high.clone()), Err(Error::KnownTransaction(_))));
+
+        // a second spend of a pooled output does not apply
+        let mut conflict = Transaction { fee: 5, outputs: vec![Output { recipient: "carol".to_string(), amount: 5 }], ..low.clone() };
+        conflict.sign(&keypair);
+        assert!(matches!(mempool.submit(&blockchain, conflict), Err(Error::MissingOutput { index: 1, .. })));
+
+        mempool.submit(&blockchain, mid.clone()).unwrap();
+        let txids: Vec<Hash> = mempool.entries().iter().map(|entry| entry.txid).collect();
+        assert_eq!(txids, vec![high.txid(), mid.txid()]);
+        assert!(matches!(mempool.submit(&blockchain, low), Err(Error::PoolFull(_))));
+    }
+
+    #[test]
+    fn pool_follows_blocks_and_reorgs() {
+        let keypair = Keypair::generate(Scheme::Ed25519);
+        let mut blockchain = funded(&keypair, &[10, 10], StateModel::Utxo);
+        let reorgs = blockchain.subscribe();
+        let clock = MockClock::new(0);
+        let mut mempool = Mempool::new();
+        mempool.set_clock(clock.clone());
+
+        let (first, second) = (spend(&keypair, &blockchain, 0, 1), spend(&keypair, &blockchain, 1, 1));
+        mempool.submit(&blockchain, first.clone()).unwrap();
+        mempool.submit(&blockchain, second.clone()).unwrap();
+
+        blockchain.add_transactions(vec![first.clone()]).unwrap();
+        mempool.sync(&blockchain);
+        assert!(!mempool.contains(&first.txid()));
+        assert!(mempool.contains(&second.txid()));
+
+        // a longer branch without the payment puts it back in the pool
+        clock.advance(DEFAULT_EXPIRY / 2);
+        let genesis = blockchain.genesis().clone();
+        let mut b1 = Block::new(&genesis, vec![Transaction::data("b1")], 1, 0, &Sha256);
+        b1.mine(&Sha256);
+        let mut b2 = Block::new(&b1, vec![Transaction::data("b2")], 2, 0, &Sha256);
+        b2.mine(&Sha256);
+        blockchain.extend_from(genesis.hash(), vec![b1, b2]).unwrap();
+        mempool.reorganized(&blockchain, &reorgs.try_recv().unwrap());
+        assert!(mempool.contains(&first.txid()));
+        assert_eq!(mempool.len(), 2);
+
+        // the payment waits again from when it came back
+        clock.advance(DEFAULT_EXPIRY / 2);
+        mempool.expire(&blockchain);
+        assert!(mempool.contains(&first.txid()));
+        assert!(!mempool.contains(&second.txid()));
+    }
+
+    #[test]
+    fn pooled_account_transactions_take_consecutive_nonces() {
+        let keypair = Keypair::generate(Scheme::Secp256k1);
+        let blockchain = funded(&keypair, &[10], StateModel::Account);
+        let pay = |nonce| {
+            let mut tx = Transaction {
+                sender: Some(keypair.public_key().address()),
+                outputs: vec![Output { recipient: "bob".to_string(), amount: 4 }],
+                nonce,
+                ..Transaction::default()
+            };
+            tx.sign(&keypair);
+            tx
+        };
+
+        let mut mempool = Mempool::new();
+        mempool.submit(&blockchain, pay(0)).unwrap();
+        assert!(matches!(mempool.submit(&blockchain, pay(2)), Err(Error::NonceMismatch { expected: 1, found: 2, .. })));
+        mempool.submit(&blockchain, pay(1)).unwrap();
+        assert!(matches!(mempool.submit(&blockchain, pay(2)), Err(Error::Overspend { available: 2, spent: 4, .. })));
+    }
+}
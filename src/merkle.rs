@@ -0,0 +1,170 @@
+use hash::{BlockHasher, Hash};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// prefixes that keep leaf and interior node hashes apart, so an interior
+// node can never be passed off as a leaf
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+fn hash_leaf(hasher: &dyn BlockHasher, leaf: &Hash) -> Hash {
+    let mut data = [0; 33];
+    data[0] = LEAF_PREFIX;
+    data[1..].copy_from_slice(leaf.as_bytes());
+    hasher.digest(&data)
+}
+
+fn hash_node(hasher: &dyn BlockHasher, left: &Hash, right: &Hash) -> Hash {
+    let mut data = [0; 65];
+    data[0] = NODE_PREFIX;
+    data[1..33].copy_from_slice(left.as_bytes());
+    data[33..].copy_from_slice(right.as_bytes());
+    hasher.digest(&data)
+}
+
+// the level above `level`, pairing nodes from the left and promoting a last
+// unpaired node as is
+fn parent_level(hasher: &dyn BlockHasher, level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match *pair {
+            [ref left, ref right] => hash_node(hasher, left, right),
+            _ => pair[0],
+        })
+        .collect()
+}
+
+fn leaf_level(hasher: &dyn BlockHasher, leaves: &[Hash]) -> Vec<Hash> {
+    leaves.iter().map(|leaf| hash_leaf(hasher, leaf)).collect()
+}
+
+/// Root of the Merkle tree over `leaves`, or the zero hash if there are none.
+///
+/// Leaves and interior nodes are hashed with different one byte prefixes.
+/// A node left without a partner is promoted to the next level rather than
+/// paired with itself, so no two different lists of leaves share a root.
+pub fn merkle_root(hasher: &dyn BlockHasher, leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::zero();
+    }
+
+    let mut level = leaf_level(hasher, leaves);
+    while level.len() > 1 {
+        level = parent_level(hasher, &level);
+    }
+    level[0]
+}
+
+/// Builds the proof that the leaf at `index` is part of the tree over
+/// `leaves`, or `None` if there is no such leaf.
+pub fn prove(hasher: &dyn BlockHasher, leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut level = leaf_level(hasher, leaves);
+    let mut position = index;
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        // a promoted node has no sibling at this level
+        let partner = position ^ 1;
+        if partner < level.len() {
+            let side = if partner < position { Side::Left } else { Side::Right };
+            siblings.push(Sibling { hash: level[partner], side });
+        }
+        level = parent_level(hasher, &level);
+        position /= 2;
+    }
+
+    Some(MerkleProof { siblings })
+}
+
+/// Which side of the path a sibling hash sits on.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A node hashed together with the path from a leaf to the root.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sibling {
+    pub hash: Hash,
+    pub side: Side,
+}
+
+/// Shows that a leaf is part of a Merkle tree without the other leaves.
+#[derive(Debug,Clone,PartialEq,Eq,Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MerkleProof {
+    /// Siblings along the path from the leaf, starting at the bottom.
+    pub siblings: Vec<Sibling>,
+}
+
+impl MerkleProof {
+    /// Whether the proof shows that `leaf` is part of the tree with `root`.
+    pub fn verify(&self, hasher: &dyn BlockHasher, leaf: &Hash, root: &Hash) -> bool {
+        let node = self.siblings.iter().fold(hash_leaf(hasher, leaf), |node, sibling| match sibling.side {
+            Side::Left => hash_node(hasher, &sibling.hash, &node),
+            Side::Right => hash_node(hasher, &node, &sibling.hash),
+        });
+        node == *root
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::Sha256;
+
+    fn leaves(count: u8) -> Vec<Hash> {
+        (0..count).map(|n| Sha256.digest(&[n])).collect()
+    }
+
+    #[test]
+    fn root_follows_tree_shape() {
+        let leaves = leaves(3);
+        assert!(merkle_root(&Sha256, &[]).is_zero());
+        assert_eq!(merkle_root(&Sha256, &leaves[..1]), hash_leaf(&Sha256, &leaves[0]));
+
+        // the third leaf is promoted, not paired with itself
+        let pair = hash_node(&Sha256, &hash_leaf(&Sha256, &leaves[0]), &hash_leaf(&Sha256, &leaves[1]));
+        let expected = hash_node(&Sha256, &pair, &hash_leaf(&Sha256, &leaves[2]));
+        assert_eq!(merkle_root(&Sha256, &leaves), expected);
+
+        let mut doubled = leaves.clone();
+        doubled.push(leaves[2]);
+        assert_ne!(merkle_root(&Sha256, &doubled), expected);
+    }
+
+    #[test]
+    fn every_leaf_has_a_proof() {
+        for count in 1..10 {
+            let leaves = leaves(count);
+            let root = merkle_root(&Sha256, &leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = prove(&Sha256, &leaves, index).unwrap();
+                assert!(proof.verify(&Sha256, leaf, &root), "leaf {} of {}", index, count);
+            }
+            assert!(prove(&Sha256, &leaves, leaves.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn proofs_reject_other_leaves_and_roots() {
+        let leaves = leaves(5);
+        let root = merkle_root(&Sha256, &leaves);
+        let proof = prove(&Sha256, &leaves, 2).unwrap();
+
+        assert!(!proof.verify(&Sha256, &leaves[3], &root));
+        assert!(!proof.verify(&Sha256, &leaves[2], &merkle_root(&Sha256, &leaves[..4])));
+
+        // an interior node does not pass as a leaf
+        let short = MerkleProof { siblings: proof.siblings[1..].to_vec() };
+        let node = hash_node(&Sha256, &hash_leaf(&Sha256, &leaves[2]), &hash_leaf(&Sha256, &leaves[3]));
+        assert!(!short.verify(&Sha256, &node, &root));
+    }
+}
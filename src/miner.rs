@@ -0,0 +1,221 @@
+use chain::Block;
+use hash::Algorithm;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tells a miner when its work has gone stale.
+///
+/// A watch is taken from `Blockchain::watch_tip` and becomes stale as soon as
+/// the chain moves to a new tip, or when `cancel` is called on it or any of
+/// its clones.
+#[derive(Debug,Clone)]
+pub struct TipWatch {
+    tip: Arc<AtomicU64>,
+    seen: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TipWatch {
+    pub(crate) fn new(tip: Arc<AtomicU64>) -> TipWatch {
+        let seen = tip.load(Ordering::SeqCst);
+        TipWatch {
+            tip,
+            seen,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Abandons the work guarded by this watch.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the chain tip changed or the watch was cancelled.
+    pub fn is_stale(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.tip.load(Ordering::Relaxed) != self.seen
+    }
+}
+
+/// Work done during one mining run.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct MiningStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    /// Hashes computed per second.
+    pub fn hash_rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Searches for proof of work on a pool of worker threads.
+#[derive(Debug,Clone,Copy)]
+pub struct Miner {
+    threads: usize,
+}
+
+impl Default for Miner {
+    fn default() -> Miner {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Miner::new(threads)
+    }
+}
+
+impl Miner {
+    /// Creates a miner that splits the nonce space across `threads` workers.
+    pub fn new(threads: usize) -> Miner {
+        Miner {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Mines `block` until its hash meets its difficulty, returning the
+    /// mined block, or `None` if `watch` went stale first.
+    ///
+    /// Worker `i` of `n` tries the nonces `i`, `i + n`, `i + 2n`, ... so
+    /// no two workers ever hash the same header.
+    pub fn mine(&self, block: &Block, algorithm: Algorithm, watch: &TipWatch) -> (Option<Block>, MiningStats) {
+        let start = Instant::now();
+        let found = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        let step = self.threads as u64;
+
+        let result = thread::scope(|scope| {
+            let workers: Vec<_> = (0..step)
+                .map(|offset| {
+                    let found = &found;
+                    let hashes = &hashes;
+                    let mut candidate = block.clone();
+
+                    scope.spawn(move || {
+                        let mut nonce = offset;
+                        let mut count = 0;
+
+                        let result = loop {
+                            if found.load(Ordering::Relaxed) || watch.is_stale() {
+                                break None;
+                            }
+
+                            count += 1;
+                            if candidate.try_nonce(nonce, &algorithm) {
+                                found.store(true, Ordering::Relaxed);
+                                break Some(candidate);
+                            }
+
+                            nonce = nonce.wrapping_add(step);
+                        };
+
+                        hashes.fetch_add(count, Ordering::Relaxed);
+                        result
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining worker panicked"))
+                .next()
+        });
+
+        let stats = MiningStats {
+            hashes: hashes.load(Ordering::SeqCst),
+            elapsed: start.elapsed(),
+        };
+
+        match result {
+            Some(ref block) => {
+                debug!(
+                    index = block.index(),
+                    nonce = block.nonce(),
+                    hashes = stats.hashes,
+                    hash_rate = stats.hash_rate(),
+                    "mined block"
+                );
+            }
+            None => {
+                debug!(
+                    index = block.index(),
+                    hashes = stats.hashes,
+                    hash_rate = stats.hash_rate(),
+                    "mining abandoned"
+                );
+            }
+        }
+
+        (result, stats)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::Blockchain;
+    use transaction::Transaction;
+    use std::sync::mpsc;
+
+    fn chain(difficulty: u32) -> Blockchain {
+        Blockchain::new(Block::genesis(vec![Transaction::data("Genesis")], 0, difficulty, &Algorithm::Sha256), Algorithm::Sha256).unwrap()
+    }
+
+    #[test]
+    fn workers_find_a_valid_block() {
+        let mut blockchain = chain(10);
+        let candidate = blockchain.candidate(vec![Transaction::data("mined")]);
+        let watch = blockchain.watch_tip();
+
+        let (block, stats) = Miner::new(4).mine(&candidate, Algorithm::Sha256, &watch);
+        let block = block.unwrap();
+
+        assert!(block.hash().meets_difficulty(10));
+        assert!(stats.hashes > 0);
+        blockchain.append(block).unwrap();
+        assert_eq!(blockchain.latest().transactions()[0].data, "mined");
+    }
+
+    #[test]
+    fn new_tip_abandons_stale_work() {
+        let mut blockchain = chain(0);
+        let candidate = Block::new(blockchain.latest(), vec![Transaction::data("never found")], 0, 256, &Algorithm::Sha256);
+        let watch = blockchain.watch_tip();
+
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let result = Miner::new(2).mine(&candidate, Algorithm::Sha256, &watch);
+            sender.send(()).unwrap();
+            result
+        });
+
+        blockchain.add_block("new tip").unwrap();
+        receiver.recv_timeout(Duration::from_secs(10)).expect("miner did not stop");
+
+        let (block, _) = worker.join().unwrap();
+        assert!(block.is_none());
+    }
+
+    #[test]
+    fn cancel_stops_mining() {
+        let blockchain = chain(0);
+        let candidate = Block::new(blockchain.latest(), vec![Transaction::data("never found")], 0, 256, &Algorithm::Sha256);
+        let watch = blockchain.watch_tip();
+        watch.cancel();
+
+        let (block, stats) = Miner::new(2).mine(&candidate, Algorithm::Sha256, &watch);
+        assert!(block.is_none());
+        assert_eq!(stats.hashes, 0);
+    }
+}
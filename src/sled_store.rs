@@ -0,0 +1,152 @@
+use chain::{Block, Error};
+use codec::Decoder;
+use hash::Hash;
+use sled::{self, Transactional};
+use sled::transaction::TransactionError;
+use store::{self, BlockStore};
+use transaction::Transaction;
+
+use std::io;
+use std::path::Path;
+
+fn db_error(err: sled::Error) -> Error {
+    Error::Io(io::Error::from(err))
+}
+
+/// A block store kept in an embedded sled database.
+///
+/// Blocks are stored in a `blocks` tree keyed by big-endian height, with a
+/// `heights` tree mapping each block hash, and a `txids` tree mapping each
+/// transaction id, to the height of its block. A rewrite updates all three
+/// trees in one transaction, so a reorganization is either stored
+/// completely or not at all.
+pub struct SledStore {
+    db: sled::Db,
+    blocks: sled::Tree,
+    heights: sled::Tree,
+    txids: sled::Tree,
+    len: usize,
+}
+
+impl SledStore {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SledStore, Error> {
+        SledStore::with_db(sled::open(path).map_err(db_error)?)
+    }
+
+    /// Stores blocks in `db`, which may be shared with other data.
+    pub fn with_db(db: sled::Db) -> Result<SledStore, Error> {
+        let blocks = db.open_tree("blocks").map_err(db_error)?;
+        let heights = db.open_tree("heights").map_err(db_error)?;
+        let txids = db.open_tree("txids").map_err(db_error)?;
+
+        // heights are stored without gaps, so the last key gives the length
+        let len = match blocks.last().map_err(db_error)? {
+            Some((key, _)) => Decoder::new(&key).u32()? as usize + 1,
+            None => 0,
+        };
+
+        Ok(SledStore { db, blocks, heights, txids, len })
+    }
+}
+
+impl BlockStore for SledStore {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, height: u32) -> Result<Option<Block>, Error> {
+        match self.blocks.get(height.to_be_bytes()).map_err(db_error)? {
+            Some(bytes) => Block::from_bytes(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, hash: &Hash) -> Result<Option<Block>, Error> {
+        match self.heights.get(hash.as_bytes()).map_err(db_error)? {
+            Some(height) => self.get(Decoder::new(&height).u32()?),
+            None => Ok(None),
+        }
+    }
+
+    fn get_transaction(&self, txid: &Hash) -> Result<Option<(u32, Transaction)>, Error> {
+        match self.txids.get(txid.as_bytes()).map_err(db_error)? {
+            Some(height) => {
+                let block = self.get(Decoder::new(&height).u32()?)?;
+                Ok(block.and_then(|block| store::find_transaction(&block, txid)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn rewrite(&mut self, from: u32, blocks: &[Block]) -> Result<(), Error> {
+        let from = (from as usize).min(self.len);
+        let mut removed = Vec::with_capacity(self.len - from);
+        for height in from..self.len {
+            let block = self.get(height as u32)?.ok_or_else(|| Error::Decode(format!("missing block {}", height)))?;
+            removed.push(block);
+        }
+        let encoded: Vec<Vec<u8>> = blocks.iter().map(Block::to_bytes).collect();
+
+        (&self.blocks, &self.heights, &self.txids)
+            .transaction(|(block_tree, height_tree, txid_tree)| {
+                for block in &removed {
+                    block_tree.remove(&block.index().to_be_bytes())?;
+                    height_tree.remove(block.hash().as_bytes())?;
+                    for tx in block.transactions() {
+                        txid_tree.remove(tx.txid().as_bytes())?;
+                    }
+                }
+                for (offset, (block, bytes)) in blocks.iter().zip(&encoded).enumerate() {
+                    let height = ((from + offset) as u32).to_be_bytes();
+                    block_tree.insert(&height, bytes.as_slice())?;
+                    height_tree.insert(block.hash().as_bytes(), &height)?;
+                    for tx in block.transactions() {
+                        txid_tree.insert(tx.txid().as_bytes(), &height)?;
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|err: TransactionError<()>| match err {
+                TransactionError::Storage(err) => db_error(err),
+                TransactionError::Abort(()) => unreachable!("block writes never abort"),
+            })?;
+
+        self.db.flush().map_err(db_error)?;
+        self.len = from + blocks.len();
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{blocks, extend};
+
+    #[test]
+    fn sled_store_rewrites_and_reopens() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let main = blocks(4, "main");
+        let fork = extend(&main[..2], 5, "fork");
+        {
+            let mut store = SledStore::with_db(db.clone()).unwrap();
+            for block in &main {
+                store.push(block).unwrap();
+            }
+            store.rewrite(2, &fork[2..]).unwrap();
+        }
+
+        // a new store finds everything the last one left in the database
+        let store = SledStore::with_db(db).unwrap();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(4).unwrap().unwrap().transactions()[0].data, "fork 4");
+        assert_eq!(store.get_by_hash(fork[3].hash()).unwrap().unwrap().index(), 3);
+        assert!(store.get_by_hash(main[3].hash()).unwrap().is_none());
+        assert!(store.get(5).unwrap().is_none());
+
+        let txid = fork[3].transactions()[0].txid();
+        assert_eq!(store.get_transaction(&txid).unwrap().unwrap().0, 3);
+        assert!(store.get_transaction(&main[3].transactions()[0].txid()).unwrap().is_none());
+    }
+}
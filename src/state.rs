@@ -0,0 +1,184 @@
+use account::{Account, AccountChanges, AccountState, AccountUndo};
+use chain::{Block, Error};
+use hash::{BlockHasher, Hash};
+use transaction::{OutPoint, Output, Transaction};
+use utxo::{BlockUndo, UtxoChanges, UtxoSet};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How a chain keeps track of who holds which coins.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Default)]
+pub enum StateModel {
+    /// Coins are held in unspent transaction outputs.
+    #[default]
+    Utxo,
+    /// Coins are held in account balances, and every block header commits
+    /// to the root of the accounts after the block.
+    Account,
+}
+
+impl fmt::Display for StateModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            StateModel::Utxo => "utxo",
+            StateModel::Account => "account",
+        })
+    }
+}
+
+impl FromStr for StateModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StateModel, String> {
+        match s {
+            "utxo" => Ok(StateModel::Utxo),
+            "account" => Ok(StateModel::Account),
+            _ => Err(format!("unknown state model '{}'", s)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_as_str!(StateModel);
+
+/// What is needed to roll a block back out of a `State`.
+#[derive(Debug,Clone)]
+pub enum StateUndo {
+    Utxo(BlockUndo),
+    Account(AccountUndo),
+}
+
+/// Changes that a run of pending transactions makes to a `State`, kept
+/// apart from it.
+#[derive(Debug,Clone)]
+pub enum PendingChanges {
+    Utxo(UtxoChanges),
+    Account(AccountChanges),
+}
+
+/// The coins held after the main chain's latest block, kept in the chain's
+/// state model.
+///
+/// Unspent output chains commit to no state root, so their blocks carry a
+/// zero `state_root`. Account chains commit every block to the root of the
+/// accounts after it.
+#[derive(Debug,Clone)]
+pub enum State {
+    Utxo(UtxoSet),
+    Account(AccountState),
+}
+
+impl State {
+    /// Creates an empty state kept in `model`.
+    pub fn new(model: StateModel) -> State {
+        match model {
+            StateModel::Utxo => State::Utxo(UtxoSet::new()),
+            StateModel::Account => State::Account(AccountState::new()),
+        }
+    }
+
+    /// The model the state is kept in.
+    pub fn model(&self) -> StateModel {
+        match *self {
+            State::Utxo(_) => StateModel::Utxo,
+            State::Account(_) => StateModel::Account,
+        }
+    }
+
+    /// The state root a header must commit to after applying `block`.
+    pub fn root_after(&self, hasher: &dyn BlockHasher, block: &Block) -> Result<Hash, Error> {
+        match *self {
+            State::Utxo(_) => Ok(Hash::zero()),
+            State::Account(ref accounts) => accounts.root_after(hasher, block),
+        }
+    }
+
+    /// Checks that `block` applies to the state and commits to the state
+    /// root that results.
+    pub fn check(&self, hasher: &dyn BlockHasher, block: &Block) -> Result<(), Error> {
+        if let State::Utxo(ref utxos) = *self {
+            utxos.check(block)?;
+        }
+        if self.root_after(hasher, block)? != *block.state_root() {
+            return Err(Error::StateRootMismatch { index: block.index() });
+        }
+        Ok(())
+    }
+
+    /// Checks that `tx`, if added to the block at height `index`, could be
+    /// applied to the state.
+    pub fn check_transaction(&self, index: u32, tx: &Transaction) -> Result<(), Error> {
+        self.apply_pending(&mut self.pending(), index, tx)
+    }
+
+    /// Creates an empty set of pending changes to the state.
+    pub fn pending(&self) -> PendingChanges {
+        match *self {
+            State::Utxo(_) => PendingChanges::Utxo(UtxoChanges::default()),
+            State::Account(_) => PendingChanges::Account(AccountChanges::default()),
+        }
+    }
+
+    /// Checks that `tx`, if added to the block at height `index` after the
+    /// transactions that made `changes`, could be applied to the state, and
+    /// adds its own changes if so.
+    pub fn apply_pending(&self, changes: &mut PendingChanges, index: u32, tx: &Transaction) -> Result<(), Error> {
+        match (self, changes) {
+            (State::Utxo(utxos), PendingChanges::Utxo(changes)) => utxos.apply_pending(changes, index, tx),
+            (State::Account(accounts), PendingChanges::Account(changes)) => accounts.apply_pending(changes, index, tx),
+            _ => panic!("pending changes do not match the state model"),
+        }
+    }
+
+    /// Applies a block that passed `check`, returning what is needed to
+    /// roll it back.
+    pub fn apply(&mut self, block: &Block) -> StateUndo {
+        match *self {
+            State::Utxo(ref mut utxos) => StateUndo::Utxo(utxos.apply(block)),
+            State::Account(ref mut accounts) => StateUndo::Account(accounts.apply(block)),
+        }
+    }
+
+    /// Rolls back `block`, the last block applied.
+    pub fn undo(&mut self, block: &Block, undo: StateUndo) {
+        match (self, undo) {
+            (&mut State::Utxo(ref mut utxos), StateUndo::Utxo(undo)) => utxos.undo(block, undo),
+            (&mut State::Account(ref mut accounts), StateUndo::Account(undo)) => accounts.undo(undo),
+            _ => panic!("undo data does not match the state model"),
+        }
+    }
+
+    /// Coins held by `address`.
+    pub fn balance(&self, address: &str) -> u64 {
+        match *self {
+            State::Utxo(ref utxos) => utxos.balance(address),
+            State::Account(ref accounts) => accounts.get(address).map_or(0, |account| account.balance),
+        }
+    }
+
+    /// Coins held by all addresses together.
+    pub fn supply(&self) -> u64 {
+        match *self {
+            State::Utxo(ref utxos) => utxos.supply(),
+            State::Account(ref accounts) => accounts.supply(),
+        }
+    }
+
+    /// The account of `address`, on account chains.
+    pub fn account(&self, address: &str) -> Option<Account> {
+        match *self {
+            State::Utxo(_) => None,
+            State::Account(ref accounts) => accounts.get(address).cloned(),
+        }
+    }
+
+    /// Unspent outputs paid to `address`, oldest first, on unspent output
+    /// chains.
+    pub fn unspent_outputs(&self, address: &str) -> Vec<(OutPoint, Output)> {
+        match *self {
+            State::Utxo(ref utxos) => utxos.unspent_outputs(address),
+            State::Account(_) => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use chain::{Block, Error};
+use codec::{Decoder, Encoder};
+use crc32fast;
+use hash::Hash;
+use transaction::Transaction;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::slice;
+
+/// Durable home for the blocks of a main chain.
+///
+/// A store holds the main chain only, ordered from genesis. Side branches
+/// are kept in memory and written once they become part of the main chain.
+pub trait BlockStore: Send {
+    /// Number of stored blocks.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the block at `height`.
+    fn get(&self, height: u32) -> Result<Option<Block>, Error>;
+
+    /// Reads the block with `hash`.
+    fn get_by_hash(&self, hash: &Hash) -> Result<Option<Block>, Error>;
+
+    /// Reads a transaction by its id, along with the height of the block
+    /// that carries it.
+    fn get_transaction(&self, txid: &Hash) -> Result<Option<(u32, Transaction)>, Error>;
+
+    /// Replaces every block from height `from` onwards with `blocks`, as a
+    /// single write that either happens completely or not at all.
+    fn rewrite(&mut self, from: u32, blocks: &[Block]) -> Result<(), Error>;
+
+    /// Stores `block` after the last stored block.
+    fn push(&mut self, block: &Block) -> Result<(), Error> {
+        let from = self.len() as u32;
+        self.rewrite(from, slice::from_ref(block))
+    }
+
+    /// Reads every stored block, ordered from genesis.
+    fn load(&self) -> Result<Vec<Block>, Error> {
+        (0..self.len() as u32)
+            .map(|height| self.get(height)?.ok_or_else(|| Error::Decode(format!("missing block {}", height))))
+            .collect()
+    }
+}
+
+/// A block store held in memory, which keeps nothing once the process exits.
+#[derive(Debug,Clone,Default)]
+pub struct MemoryStore {
+    blocks: Vec<Block>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl BlockStore for MemoryStore {
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn get(&self, height: u32) -> Result<Option<Block>, Error> {
+        Ok(self.blocks.get(height as usize).cloned())
+    }
+
+    fn get_by_hash(&self, hash: &Hash) -> Result<Option<Block>, Error> {
+        Ok(self.blocks.iter().find(|block| block.hash() == hash).cloned())
+    }
+
+    fn get_transaction(&self, txid: &Hash) -> Result<Option<(u32, Transaction)>, Error> {
+        Ok(self.blocks.iter().find_map(|block| find_transaction(block, txid)))
+    }
+
+    fn rewrite(&mut self, from: u32, blocks: &[Block]) -> Result<(), Error> {
+        self.blocks.truncate(from as usize);
+        self.blocks.extend_from_slice(blocks);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<Block>, Error> {
+        Ok(self.blocks.clone())
+    }
+}
+
+// the transaction with `txid` in `block`, with the block's height
+pub(crate) fn find_transaction(block: &Block, txid: &Hash) -> Option<(u32, Transaction)> {
+    block.transactions().iter().find(|tx| tx.txid() == *txid).map(|tx| (block.index(), tx.clone()))
+}
+
+const MAGIC: &[u8; 8] = b"BLOCKLOG";
+const FORMAT_VERSION: u32 = 1;
+const FILE_HEADER_LEN: u64 = 12;
+const RECORD_HEADER_LEN: u64 = 12;
+
+// where a block's encoding sits in the log
+#[derive(Debug,Clone)]
+struct Entry {
+    offset: u64,
+    len: u64,
+    hash: Hash,
+    txids: Vec<Hash>,
+}
+
+/// A block store kept in a single append-only log file.
+///
+/// The file starts with an 8 byte magic and a format version, followed by
+/// records. Each record is the `u32` length of its body, the CRC-32 of that
+/// length and the CRC-32 of the body, then the body: the height to rewrite
+/// from, the number of blocks and each block's length-prefixed
+/// `Block::to_bytes` encoding. Appending a block writes a record that
+/// rewrites from the end of the chain, and a reorganization writes one
+/// record holding the whole new branch, so it is applied as a unit.
+///
+/// Every record is synced to disk before the write returns. The index by
+/// height, block hash and transaction id is kept in memory and rebuilt by
+/// replaying the log on open. A record cut short or failing its body
+/// checksum at the end of the log is the remains of an interrupted write
+/// and is truncated away; a damaged length, or damage anywhere else, is
+/// reported as `Error::CorruptStore`.
+#[derive(Debug)]
+pub struct FileStore {
+    file: File,
+    entries: Vec<Entry>,
+    heights: HashMap<Hash, u32>,
+    txids: HashMap<Hash, u32>,
+}
+
+impl FileStore {
+    /// Opens the log at `path`, creating an empty one if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileStore, Error> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path.as_ref())?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        if contents.is_empty() {
+            let header = Encoder::new().u32(FORMAT_VERSION).finish();
+            file.write_all(MAGIC)?;
+            file.write_all(&header)?;
+            file.sync_all()?;
+            contents.extend_from_slice(MAGIC);
+            contents.extend_from_slice(&header);
+        }
+
+        let mut store = FileStore {
+            file,
+            entries: Vec::new(),
+            heights: HashMap::new(),
+            txids: HashMap::new(),
+        };
+        store.replay(&contents)?;
+        debug!(path = %path.as_ref().display(), blocks = store.entries.len(), "opened block log");
+        Ok(store)
+    }
+
+    // rebuilds the index from the log contents
+    fn replay(&mut self, contents: &[u8]) -> Result<(), Error> {
+        if contents.len() < FILE_HEADER_LEN as usize || &contents[..8] != MAGIC {
+            return Err(Error::CorruptStore { offset: 0 });
+        }
+        let version = Decoder::new(&contents[8..12]).u32()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Decode(format!("unsupported block log version {}", version)));
+        }
+
+        let end = contents.len() as u64;
+        let mut offset = FILE_HEADER_LEN;
+        while offset < end {
+            let header = match contents.get(offset as usize..(offset + RECORD_HEADER_LEN) as usize) {
+                Some(header) => header,
+                None => return self.drop_tail(offset, end),
+            };
+            let mut decoder = Decoder::new(header);
+            let (len, len_checksum, checksum) = (u64::from(decoder.u32()?), decoder.u32()?, decoder.u32()?);
+            // the length is trusted to find the end of the log, so it has to be intact
+            if crc32fast::hash(&header[..4]) != len_checksum {
+                return Err(Error::CorruptStore { offset });
+            }
+
+            let body_offset = offset + RECORD_HEADER_LEN;
+            match contents.get(body_offset as usize..(body_offset + len) as usize) {
+                Some(body) if crc32fast::hash(body) == checksum => {
+                    self.apply(body, body_offset).map_err(|_| Error::CorruptStore { offset })?;
+                    offset = body_offset + len;
+                }
+                // only the last record can be left damaged by an interrupted write
+                _ if body_offset + len >= end => return self.drop_tail(offset, end),
+                _ => return Err(Error::CorruptStore { offset }),
+            }
+        }
+
+        Ok(())
+    }
+
+    // cuts off the remains of an interrupted write from `offset` on
+    fn drop_tail(&mut self, offset: u64, end: u64) -> Result<(), Error> {
+        warn!(offset, dropped = end - offset, "truncating interrupted write from block log");
+        self.file.set_len(offset)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    // updates the index with a record body found at `body_offset`
+    fn apply(&mut self, body: &[u8], body_offset: u64) -> Result<(), Error> {
+        let mut decoder = Decoder::new(body);
+        let from = decoder.u64()?;
+        if from > self.entries.len() as u64 {
+            return Err(Error::Decode(format!("rewrite from {} past the end of the chain", from)));
+        }
+        self.unindex(from as usize);
+
+        let count = decoder.u64()?;
+        for _ in 0..count {
+            let bytes = decoder.bytes()?;
+            let block = Block::from_bytes(bytes)?;
+            let offset = body_offset + (body.len() - decoder.remaining() - bytes.len()) as u64;
+            self.index(&block, offset, bytes.len() as u64);
+        }
+        decoder.finish()
+    }
+
+    fn index(&mut self, block: &Block, offset: u64, len: u64) {
+        let height = self.entries.len() as u32;
+        let txids: Vec<Hash> = block.transactions().iter().map(Transaction::txid).collect();
+        for txid in &txids {
+            self.txids.insert(*txid, height);
+        }
+        self.heights.insert(*block.hash(), height);
+        self.entries.push(Entry { offset, len, hash: *block.hash(), txids });
+    }
+
+    fn unindex(&mut self, from: usize) {
+        for entry in self.entries.drain(from..) {
+            self.heights.remove(&entry.hash);
+            for txid in &entry.txids {
+                self.txids.remove(txid);
+            }
+        }
+    }
+
+    fn read(&self, entry: &Entry) -> Result<Block, Error> {
+        let mut file = &self.file;
+        let mut bytes = vec![0; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut bytes)?;
+        Block::from_bytes(&bytes)
+    }
+}
+
+impl BlockStore for FileStore {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&self, height: u32) -> Result<Option<Block>, Error> {
+        match self.entries.get(height as usize) {
+            Some(entry) => self.read(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, hash: &Hash) -> Result<Option<Block>, Error> {
+        match self.heights.get(hash) {
+            Some(&height) => self.get(height),
+            None => Ok(None),
+        }
+    }
+
+    fn get_transaction(&self, txid: &Hash) -> Result<Option<(u32, Transaction)>, Error> {
+        match self.txids.get(txid) {
+            Some(&height) => Ok(self.get(height)?.and_then(|block| find_transaction(&block, txid))),
+            None => Ok(None),
+        }
+    }
+
+    fn rewrite(&mut self, from: u32, blocks: &[Block]) -> Result<(), Error> {
+        let from = (from as usize).min(self.entries.len());
+        let mut encoder = Encoder::new();
+        encoder.u64(from as u64).u64(blocks.len() as u64);
+        let encoded: Vec<Vec<u8>> = blocks.iter().map(Block::to_bytes).collect();
+        for bytes in &encoded {
+            encoder.bytes(bytes);
+        }
+        let body = encoder.finish();
+
+        let record_offset = self.file.seek(SeekFrom::End(0))?;
+        let mut record = Encoder::new().u32(body.len() as u32).finish();
+        let len_checksum = crc32fast::hash(&record);
+        record.extend_from_slice(&Encoder::new().u32(len_checksum).u32(crc32fast::hash(&body)).finish());
+        record.extend_from_slice(&body);
+        if let Err(err) = self.file.write_all(&record).and_then(|()| self.file.sync_data()) {
+            // a partial record left behind would be followed by the next one,
+            // and could no longer be told apart from damage
+            if let Err(truncate_err) = self.file.set_len(record_offset) {
+                warn!(offset = record_offset, error = %truncate_err, "could not remove a failed write from block log");
+            }
+            return Err(err.into());
+        }
+
+        // block encodings follow the rewrite height, the count and their own length
+        self.unindex(from);
+        let mut offset = record_offset + RECORD_HEADER_LEN + 16;
+        for (block, bytes) in blocks.iter().zip(&encoded) {
+            offset += 8;
+            self.index(block, offset, bytes.len() as u64);
+            offset += bytes.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{blocks, extend};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("blockchain-{}-{}.log", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn file_store_survives_reopening() {
+        let path = temp_path("reopen");
+        let main = blocks(4, "main");
+        let fork = extend(&main[..2], 5, "fork");
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            for block in &main {
+                store.push(block).unwrap();
+            }
+            store.rewrite(2, &fork[2..]).unwrap();
+        }
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(4).unwrap().unwrap().transactions()[0].data, "fork 4");
+        assert_eq!(store.get_by_hash(fork[3].hash()).unwrap().unwrap().index(), 3);
+        assert!(store.get_by_hash(main[3].hash()).unwrap().is_none());
+
+        let txid = fork[3].transactions()[0].txid();
+        assert_eq!(store.get_transaction(&txid).unwrap().unwrap().0, 3);
+        assert!(store.get_transaction(&main[3].transactions()[0].txid()).unwrap().is_none());
+
+        let loaded: Vec<Hash> = store.load().unwrap().iter().map(|block| *block.hash()).collect();
+        let expected: Vec<Hash> = fork.iter().map(|block| *block.hash()).collect();
+        assert_eq!(loaded, expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn interrupted_writes_are_dropped() {
+        let path = temp_path("torn");
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            for block in &blocks(3, "main") {
+                store.push(block).unwrap();
+            }
+        }
+
+        // cut the last record short, as a crash mid-write would
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        store.push(&blocks(3, "main")[2]).unwrap();
+        assert_eq!(FileStore::open(&path).unwrap().len(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_records_are_reported() {
+        let path = temp_path("corrupt");
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            for block in &blocks(3, "main") {
+                store.push(block).unwrap();
+            }
+        }
+
+        // flip a byte inside the first record's body
+        let mut contents = fs::read(&path).unwrap();
+        contents[FILE_HEADER_LEN as usize + RECORD_HEADER_LEN as usize + 20] ^= 0xff;
+        fs::write(&path, &contents).unwrap();
+
+        match FileStore::open(&path) {
+            Err(Error::CorruptStore { offset }) => assert_eq!(offset, FILE_HEADER_LEN),
+            other => panic!("expected CorruptStore, got {:?}", other),
+        }
+
+        // a damaged length is not mistaken for the end of the log, even when
+        // it points past it
+        contents[FILE_HEADER_LEN as usize + RECORD_HEADER_LEN as usize + 20] ^= 0xff;
+        contents[FILE_HEADER_LEN as usize] ^= 0x40;
+        fs::write(&path, &contents).unwrap();
+        match FileStore::open(&path) {
+            Err(Error::CorruptStore { offset }) => assert_eq!(offset, FILE_HEADER_LEN),
+            other => panic!("expected CorruptStore, got {:?}", other),
+        }
+
+        // logs written in another format are refused outright
+        contents[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        fs::write(&path, &contents).unwrap();
+        assert!(matches!(FileStore::open(&path), Err(Error::Decode(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+// fixtures shared by the unit tests of several modules
+
+use chain::{Block, Blockchain};
+use genesis::{Allocation, GenesisSpec};
+use hash::{Algorithm, Sha256};
+use keys::Keypair;
+use state::StateModel;
+use transaction::{Output, Transaction};
+
+// a genesis block crediting `amount` coins to `address`
+pub fn allocate(address: &str, amount: u64) -> Block {
+    let tx = Transaction {
+        outputs: vec![Output { recipient: address.to_string(), amount }],
+        ..Transaction::data("Genesis")
+    };
+    Block::genesis(vec![tx], 0, 0, &Sha256)
+}
+
+// extends `base`, or a fresh genesis block, to `count` blocks
+pub fn extend(base: &[Block], count: usize, tag: &str) -> Vec<Block> {
+    let mut blocks = base.to_vec();
+    if blocks.is_empty() {
+        blocks.push(Block::genesis(vec![Transaction::data("Genesis")], 0, 0, &Algorithm::Sha256));
+    }
+    for index in blocks.len()..count {
+        let payload = format!("{} {}", tag, index);
+        let block = Block::new(&blocks[index - 1], vec![Transaction::data(&payload)], index as u64, 0, &Algorithm::Sha256);
+        blocks.push(block);
+    }
+    blocks
+}
+
+pub fn blocks(count: usize, tag: &str) -> Vec<Block> {
+    extend(&[], count, tag)
+}
+
+// a chain whose genesis block pays `keypair` one output per amount
+pub fn funded(keypair: &Keypair, amounts: &[u64], state_model: StateModel) -> Blockchain {
+    let address = keypair.public_key().address();
+    let spec = GenesisSpec {
+        state_model,
+        allocations: amounts.iter().map(|&amount| Allocation { address: address.clone(), amount }).collect(),
+        ..GenesisSpec::default()
+    };
+    Blockchain::from_spec(&spec).unwrap()
+}
@@ -0,0 +1,354 @@
+use chain::Error;
+use codec::{Decoder, Encoder};
+use hash::{BlockHasher, Hash, Sha256};
+use keys::{Keypair, PublicKey, Signature};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+/// Largest `data` a transaction may carry, in bytes.
+pub const MAX_DATA_LEN: usize = 64 * 1024;
+
+/// Reference to an output of an earlier transaction.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OutPoint {
+    /// Id of the transaction holding the output.
+    pub txid: Hash,
+    /// Position of the output in that transaction.
+    pub index: u32,
+}
+
+/// Coins paid to an address.
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Output {
+    pub recipient: String,
+    pub amount: u64,
+}
+
+/// The signature authorizing a transaction, with the key that made it.
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Witness {
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+/// A transfer of coins, a piece of data, or both.
+///
+/// Coins are paid out of either the unspent outputs listed in `inputs` or
+/// the balance of the `sender` account, never both. A transaction with no
+/// outputs and no fee needs neither and just records its `data`.
+///
+/// A transaction that spends coins must carry a `witness` signed over its
+/// `signing_hash`. When coins come from an account, the witness key must
+/// belong to the `sender` address.
+#[derive(Debug,Clone,PartialEq,Eq,Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Transaction {
+    /// Earlier outputs spent by this transaction.
+    pub inputs: Vec<OutPoint>,
+    /// Account paying for this transaction.
+    pub sender: Option<String>,
+    pub outputs: Vec<Output>,
+    /// Amount left for the miner of the block.
+    pub fee: u64,
+    /// Sequence number among the sender's transactions, which also keeps
+    /// otherwise identical transactions apart.
+    pub nonce: u64,
+    /// Arbitrary data carried by the transaction.
+    pub data: String,
+    pub witness: Option<Witness>,
+}
+
+impl Transaction {
+    /// Creates a transaction that only carries `data`.
+    pub fn data(data: &str) -> Transaction {
+        Transaction {
+            data: data.to_string(),
+            ..Transaction::default()
+        }
+    }
+
+    /// Creates the coinbase of the block at height `index`, paying `amount`
+    /// to `recipient`. The height keeps the coinbases of different blocks
+    /// apart.
+    pub fn coinbase(index: u32, recipient: &str, amount: u64) -> Transaction {
+        Transaction {
+            outputs: vec![Output { recipient: recipient.to_string(), amount }],
+            nonce: u64::from(index),
+            ..Transaction::default()
+        }
+    }
+
+    /// Whether the transaction pays out coins with neither inputs nor a
+    /// sender, which only the first transaction of a block may do.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty() && self.sender.is_none() && !self.outputs.is_empty()
+    }
+
+    /// Identifies the transaction by the SHA-256 hash of its encoding,
+    /// whichever hash function its chain uses for blocks.
+    pub fn txid(&self) -> Hash {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        Sha256.digest(&encoder.finish())
+    }
+
+    /// The SHA-256 hash of everything but the witness, which is what the
+    /// witness signs.
+    pub fn signing_hash(&self) -> Hash {
+        let mut encoder = Encoder::new();
+        self.encode_unsigned(&mut encoder);
+        Sha256.digest(&encoder.finish())
+    }
+
+    /// Signs the transaction with `keypair`, replacing any earlier witness.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        let signature = keypair.sign(self.signing_hash().as_bytes());
+        self.witness = Some(Witness { public_key: keypair.public_key(), signature });
+    }
+
+    /// Whether the transaction carries the signature it needs: one is
+    /// required to spend coins, and any witness present must verify.
+    pub fn is_signed(&self) -> bool {
+        let witness = match self.witness {
+            Some(ref witness) => witness,
+            None => return self.inputs.is_empty() && self.sender.is_none(),
+        };
+
+        if let Some(ref sender) = self.sender {
+            if *sender != witness.public_key.address() {
+                return false;
+            }
+        }
+
+        witness.public_key.verify(self.signing_hash().as_bytes(), &witness.signature)
+    }
+
+    /// Length of the transaction's encoding, in bytes.
+    pub fn size(&self) -> usize {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        encoder.finish().len()
+    }
+
+    /// Sum of the outputs and the fee, or `None` if it overflows.
+    pub fn total_spent(&self) -> Option<u64> {
+        self.outputs.iter().try_fold(self.fee, |total, output| total.checked_add(output.amount))
+    }
+
+    /// Checks the rules a transaction must follow on its own, without
+    /// looking at the chain it is added to.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.inputs.is_empty() && self.sender.is_some() {
+            return Err("spends both outputs and an account balance".to_string());
+        }
+
+        let funded = !self.inputs.is_empty() || self.sender.is_some();
+        if !funded && (self.fee > 0 || !self.outputs.is_empty()) {
+            return Err("pays out coins without inputs or a sender".to_string());
+        }
+
+        self.check_contents()
+    }
+
+    /// Checks the rules a block's coinbase must follow on its own: it pays
+    /// out coins from neither inputs nor a sender and leaves no fee.
+    pub fn check_coinbase(&self) -> Result<(), String> {
+        if !self.inputs.is_empty() || self.sender.is_some() {
+            return Err("coinbase spends inputs or an account balance".to_string());
+        }
+
+        if self.fee > 0 {
+            return Err("coinbase leaves a fee".to_string());
+        }
+
+        self.check_contents()
+    }
+
+    // rules on the outputs, inputs and data of any transaction
+    fn check_contents(&self) -> Result<(), String> {
+        if self.outputs.iter().any(|output| output.amount == 0) {
+            return Err("has an output of zero coins".to_string());
+        }
+
+        if self.total_spent().is_none() {
+            return Err("spends more coins than can be counted".to_string());
+        }
+
+        let mut spent = HashSet::new();
+        if !self.inputs.iter().all(|input| spent.insert(input)) {
+            return Err("spends the same output twice".to_string());
+        }
+
+        if self.data.len() > MAX_DATA_LEN {
+            return Err(format!("carries {} bytes of data, more than {}", self.data.len(), MAX_DATA_LEN));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        self.encode_unsigned(encoder);
+        match self.witness {
+            Some(ref witness) => {
+                encoder.u32(1);
+                witness.public_key.encode(encoder);
+                witness.signature.encode(encoder);
+            }
+            None => {
+                encoder.u32(0);
+            }
+        }
+    }
+
+    fn encode_unsigned(&self, encoder: &mut Encoder) {
+        encoder.u64(self.inputs.len() as u64);
+        for input in &self.inputs {
+            encoder.hash(&input.txid).u32(input.index);
+        }
+
+        match self.sender {
+            Some(ref sender) => encoder.u32(1).bytes(sender.as_bytes()),
+            None => encoder.u32(0),
+        };
+
+        encoder.u64(self.outputs.len() as u64);
+        for output in &self.outputs {
+            encoder.bytes(output.recipient.as_bytes()).u64(output.amount);
+        }
+
+        encoder.u64(self.fee).u64(self.nonce).bytes(self.data.as_bytes());
+    }
+
+    pub(crate) fn decode(decoder: &mut Decoder) -> Result<Transaction, Error> {
+        let mut inputs = Vec::new();
+        for _ in 0..decoder.u64()? {
+            inputs.push(OutPoint {
+                txid: decoder.hash()?,
+                index: decoder.u32()?,
+            });
+        }
+
+        let sender = match decoder.u32()? {
+            0 => None,
+            1 => Some(decoder.string()?),
+            flag => return Err(Error::Decode(format!("invalid sender flag {}", flag))),
+        };
+
+        let mut outputs = Vec::new();
+        for _ in 0..decoder.u64()? {
+            outputs.push(Output {
+                recipient: decoder.string()?,
+                amount: decoder.u64()?,
+            });
+        }
+
+        let fee = decoder.u64()?;
+        let nonce = decoder.u64()?;
+        let data = decoder.string()?;
+
+        let witness = match decoder.u32()? {
+            0 => None,
+            1 => Some(Witness {
+                public_key: PublicKey::decode(decoder)?,
+                signature: Signature::decode(decoder)?,
+            }),
+            flag => return Err(Error::Decode(format!("invalid witness flag {}", flag))),
+        };
+
+        Ok(Transaction { inputs, sender, outputs, fee, nonce, data, witness })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::Scheme;
+
+    fn payment() -> Transaction {
+        Transaction {
+            sender: Some("alice".to_string()),
+            outputs: vec![Output { recipient: "bob".to_string(), amount: 10 }],
+            fee: 1,
+            nonce: 7,
+            data: "rent".to_string(),
+            ..Transaction::default()
+        }
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let tx = payment();
+        let mut encoder = Encoder::new();
+        tx.encode(&mut encoder);
+        let bytes = encoder.finish();
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(Transaction::decode(&mut decoder).unwrap(), tx);
+        assert!(decoder.finish().is_ok());
+
+        let mut other = tx.clone();
+        other.nonce += 1;
+        assert_ne!(other.txid(), tx.txid());
+    }
+
+    #[test]
+    fn signatures_cover_the_transaction() {
+        let keypair = Keypair::generate(Scheme::Secp256k1);
+        let mut tx = Transaction { sender: Some(keypair.public_key().address()), ..payment() };
+        assert!(!tx.is_signed());
+
+        tx.sign(&keypair);
+        assert!(tx.is_signed());
+
+        let mut bytes = Encoder::new();
+        tx.encode(&mut bytes);
+        assert_eq!(Transaction::decode(&mut Decoder::new(&bytes.finish())).unwrap(), tx);
+
+        let mut altered = tx.clone();
+        altered.fee += 1;
+        assert!(!altered.is_signed());
+
+        // the key must belong to the sender
+        let mut stolen = Transaction { sender: Some(keypair.public_key().address()), ..payment() };
+        stolen.sign(&Keypair::generate(Scheme::Ed25519));
+        assert!(!stolen.is_signed());
+
+        assert!(Transaction::data("hello").is_signed());
+    }
+
+    #[test]
+    fn check_rejects_malformed_transactions() {
+        assert!(payment().check().is_ok());
+        assert!(Transaction::data("hello").check().is_ok());
+
+        let unfunded = Transaction { sender: None, ..payment() };
+        assert!(unfunded.check().is_err());
+
+        let input = OutPoint { txid: Hash::zero(), index: 0 };
+        let both = Transaction { inputs: vec![input], ..payment() };
+        assert!(both.check().is_err());
+
+        let twice = Transaction { inputs: vec![input, input], sender: None, ..payment() };
+        assert!(twice.check().is_err());
+
+        let overflow = Transaction { fee: u64::MAX, ..payment() };
+        assert!(overflow.check().is_err());
+
+        let zero = Transaction { outputs: vec![Output { recipient: "bob".to_string(), amount: 0 }], ..payment() };
+        assert!(zero.check().is_err());
+
+        // only a block's coinbase may pay out coins from nowhere
+        let coinbase = Transaction::coinbase(1, "miner", 5);
+        assert!(coinbase.check().is_err());
+        assert!(coinbase.check_coinbase().is_ok());
+        assert_ne!(coinbase.txid(), Transaction::coinbase(2, "miner", 5).txid());
+        assert!(Transaction { fee: 1, ..coinbase }.check_coinbase().is_err());
+        assert!(payment().check_coinbase().is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use chain::{Block, Error};
+use hash::Hash;
+use transaction::{OutPoint, Output, Transaction};
+
+use std::collections::{HashMap, HashSet};
+
+/// An unspent output, with the height of the block that created it.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Coin {
+    pub output: Output,
+    pub height: u32,
+}
+
+/// The coins a block spent, kept so the block can be rolled back.
+#[derive(Debug,Clone,Default)]
+pub struct BlockUndo {
+    // in the order the block spent them
+    spent: Vec<(OutPoint, Coin)>,
+}
+
+// checks that `tx` only spends outputs found by `lookup`, each paid to the
+// key that signed it, and that its outputs and fee add up to exactly what
+// they hold, since anything left over would belong to no one
+fn check_spends<'a, F>(index: u32, txid: Hash, tx: &Transaction, lookup: F) -> Result<(), Error>
+where
+    F: Fn(&OutPoint) -> Option<&'a Output>,
+{
+    if tx.sender.is_some() {
+        let reason = "spends an account balance, which an unspent output chain does not keep".to_string();
+        return Err(Error::InvalidTransaction { index, txid, reason });
+    }
+    if tx.inputs.is_empty() {
+        return Ok(());
+    }
+
+    let owner = tx.witness.as_ref().map(|witness| witness.public_key.address());
+    let mut available: u64 = 0;
+    for input in &tx.inputs {
+        let output = lookup(input).ok_or(Error::MissingOutput { index, txid, input: *input })?;
+        if owner.as_ref() != Some(&output.recipient) {
+            return Err(Error::ForeignOutput { index, txid, input: *input });
+        }
+        available = available.saturating_add(output.amount);
+    }
+
+    let spent = tx.total_spent().unwrap_or(u64::MAX);
+    if spent > available {
+        return Err(Error::Overspend { index, txid, available, spent });
+    }
+    if spent < available {
+        return Err(Error::Underspend { index, txid, available, spent });
+    }
+    Ok(())
+}
+
+/// Outputs created and spent by a run of transactions on top of a
+/// `UtxoSet`, kept apart from the set itself.
+#[derive(Debug,Clone,Default)]
+pub struct UtxoChanges {
+    created: HashMap<OutPoint, Output>,
+    spent: HashSet<OutPoint>,
+}
+
+/// The outputs of main chain transactions that no later transaction spends.
+///
+/// Every block appended to the chain is checked against the set and then
+/// applied to it, which removes the outputs the block spends and adds the
+/// ones it creates. Applying a block returns the coins it spent, so that a
+/// reorganization can roll the block back.
+#[derive(Debug,Clone,Default)]
+pub struct UtxoSet {
+    coins: HashMap<OutPoint, Coin>,
+}
+
+impl UtxoSet {
+    /// Creates an empty set.
+    pub fn new() -> UtxoSet {
+        UtxoSet::default()
+    }
+
+    /// Number of unspent outputs.
+    pub fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    /// Whether there are no unspent outputs.
+    pub fn is_empty(&self) -> bool {
+        self.coins.is_empty()
+    }
+
+    /// Looks up an unspent output.
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Coin> {
+        self.coins.get(outpoint)
+    }
+
+    /// Unspent outputs paid to `address`, oldest first.
+    pub fn unspent_outputs(&self, address: &str) -> Vec<(OutPoint, Output)> {
+        let mut unspent: Vec<(&OutPoint, &Coin)> = self.coins
+            .iter()
+            .filter(|(_, coin)| coin.output.recipient == address)
+            .collect();
+        unspent.sort_by_key(|(outpoint, coin)| (coin.height, **outpoint));
+        unspent.into_iter().map(|(outpoint, coin)| (*outpoint, coin.output.clone())).collect()
+    }
+
+    /// Coins held by `address` in unspent outputs.
+    pub fn balance(&self, address: &str) -> u64 {
+        self.coins
+            .values()
+            .filter(|coin| coin.output.recipient == address)
+            .fold(0, |total: u64, coin| total.saturating_add(coin.output.amount))
+    }
+
+    /// Coins held in all unspent outputs.
+    pub fn supply(&self) -> u64 {
+        self.coins.values().fold(0, |total, coin| total.saturating_add(coin.output.amount))
+    }
+
+    /// Checks that `tx`, if added to the block at height `index`, would only
+    /// spend outputs in the set.
+    pub fn check_transaction(&self, index: u32, tx: &Transaction) -> Result<(), Error> {
+        self.apply_pending(&mut UtxoChanges::default(), index, tx)
+    }
+
+    /// Checks that `tx`, if added to the block at height `index` after the
+    /// transactions that made `changes`, would only spend outputs that are
+    /// in the set or created by them and not yet spent, and adds its own
+    /// changes if so.
+    pub fn apply_pending(&self, changes: &mut UtxoChanges, index: u32, tx: &Transaction) -> Result<(), Error> {
+        let txid = tx.txid();
+        check_spends(index, txid, tx, |input| {
+            if changes.spent.contains(input) {
+                return None;
+            }
+            changes.created.get(input).or_else(|| self.coins.get(input).map(|coin| &coin.output))
+        })?;
+
+        changes.spent.extend(tx.inputs.iter().cloned());
+        for (position, output) in tx.outputs.iter().enumerate() {
+            changes.created.insert(OutPoint { txid, index: position as u32 }, output.clone());
+        }
+        Ok(())
+    }
+
+    /// Checks that every transaction in `block` spends outputs that are in
+    /// the set or created earlier in the block, none more than once, each
+    /// signed for by the key it was paid to, and no more coins than they
+    /// hold. Account-funded transactions are rejected.
+    pub fn check(&self, block: &Block) -> Result<(), Error> {
+        let mut changes = UtxoChanges::default();
+        for tx in block.transactions() {
+            self.apply_pending(&mut changes, block.index(), tx)?;
+        }
+        Ok(())
+    }
+
+    /// Applies a block that passed `check`, returning what is needed to
+    /// roll it back.
+    pub fn apply(&mut self, block: &Block) -> BlockUndo {
+        let mut undo = BlockUndo::default();
+        for tx in block.transactions() {
+            for input in &tx.inputs {
+                if let Some(coin) = self.coins.remove(input) {
+                    undo.spent.push((*input, coin));
+                }
+            }
+
+            let txid = tx.txid();
+            for (position, output) in tx.outputs.iter().enumerate() {
+                let coin = Coin { output: output.clone(), height: block.index() };
+                self.coins.insert(OutPoint { txid, index: position as u32 }, coin);
+            }
+        }
+        undo
+    }
+
+    /// Rolls back `block`, the last block applied, with the undo data its
+    /// `apply` returned.
+    pub fn undo(&mut self, block: &Block, mut undo: BlockUndo) {
+        // newest transaction first, so outputs spent within the block come back
+        for tx in block.transactions().iter().rev() {
+            let txid = tx.txid();
+            for position in 0..tx.outputs.len() {
+                self.coins.remove(&OutPoint { txid, index: position as u32 });
+            }
+
+            for _ in &tx.inputs {
+                let (outpoint, coin) = undo.spent.pop().expect("undo data covers every input");
+                self.coins.insert(outpoint, coin);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::Sha256;
+    use keys::{Keypair, Scheme};
+    use test_utils::allocate;
+
+    fn pay(keypair: &Keypair, inputs: Vec<OutPoint>, outputs: Vec<(&str, u64)>) -> Transaction {
+        let mut tx = Transaction {
+            inputs,
+            outputs: outputs.into_iter().map(|(recipient, amount)| Output { recipient: recipient.to_string(), amount }).collect(),
+            ..Transaction::default()
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    #[test]
+    fn undo_restores_spent_outputs() {
+        let alice = Keypair::generate(Scheme::Ed25519);
+        let bob = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&alice.public_key().address(), 10);
+        let coin = OutPoint { txid: genesis.transactions()[0].txid(), index: 0 };
+
+        let mut utxos = UtxoSet::new();
+        utxos.apply(&genesis);
+        let before = utxos.clone();
+
+        // bob spends an output created earlier in the same block
+        let first = pay(&alice, vec![coin], vec![(&bob.public_key().address(), 10)]);
+        let second = pay(&bob, vec![OutPoint { txid: first.txid(), index: 0 }], vec![("carol", 10)]);
+        let block = Block::new(&genesis, vec![first, second], 1, 0, &Sha256);
+        utxos.check(&block).unwrap();
+
+        let undo = utxos.apply(&block);
+        assert!(utxos.get(&coin).is_none());
+        assert_eq!(utxos.balance("carol"), 10);
+        assert_eq!(utxos.len(), 1);
+
+        utxos.undo(&block, undo);
+        assert_eq!(utxos.coins, before.coins);
+        assert_eq!(utxos.unspent_outputs(&alice.public_key().address()), vec![(coin, genesis.transactions()[0].outputs[0].clone())]);
+    }
+
+    #[test]
+    fn check_rejects_bad_spends() {
+        let alice = Keypair::generate(Scheme::Ed25519);
+        let genesis = allocate(&alice.public_key().address(), 10);
+        let coin = OutPoint { txid: genesis.transactions()[0].txid(), index: 0 };
+        let mut utxos = UtxoSet::new();
+        utxos.apply(&genesis);
+        let block = |transactions| Block::new(&genesis, transactions, 1, 0, &Sha256);
+
+        let missing = OutPoint { txid: Hash::zero(), index: 0 };
+        let result = utxos.check(&block(vec![pay(&alice, vec![missing], vec![("bob", 1)])]));
+        assert!(matches!(result, Err(Error::MissingOutput { index: 1, .. })));
+
+        let twice = vec![pay(&alice, vec![coin], vec![("bob", 10)]), pay(&alice, vec![coin], vec![("carol", 10)])];
+        assert!(matches!(utxos.check(&block(twice)), Err(Error::MissingOutput { .. })));
+
+        let thief = Keypair::generate(Scheme::Secp256k1);
+        let stolen = pay(&thief, vec![coin], vec![("mallory", 10)]);
+        assert!(matches!(utxos.check(&block(vec![stolen])), Err(Error::ForeignOutput { .. })));
+
+        let inflated = pay(&alice, vec![coin], vec![("bob", 11)]);
+        assert!(matches!(utxos.check(&block(vec![inflated])), Err(Error::Overspend { available: 10, spent: 11, .. })));
+
+        let wasteful = pay(&alice, vec![coin], vec![("bob", 9)]);
+        assert!(matches!(utxos.check(&block(vec![wasteful])), Err(Error::Underspend { available: 10, spent: 9, .. })));
+        let exact = pay(&alice, vec![coin], vec![("bob", 9), ("alice", 1)]);
+        assert!(utxos.check(&block(vec![exact])).is_ok());
+
+        let account = Transaction { sender: Some("alice".to_string()), ..Transaction::default() };
+        assert!(matches!(utxos.check_transaction(1, &account), Err(Error::InvalidTransaction { .. })));
+    }
+}
@@ -0,0 +1,344 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chain::{Blockchain, Error};
+use codec::{Decoder, Encoder};
+use keys::{self, Keypair, Scheme};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use scrypt;
+use state::StateModel;
+use transaction::{Output, Transaction};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"KEYSTORE";
+const FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Scrypt cost used when saving a keystore, as the log2 of the number of
+/// iterations. Each step up doubles the time to try a passphrase.
+pub const DEFAULT_WORK_FACTOR: u8 = 15;
+
+/// Highest scrypt work factor a keystore may use. Deriving its key already
+/// takes a gigabyte of memory.
+pub const MAX_WORK_FACTOR: u8 = 20;
+
+// scrypt block size and parallelism, kept at the usual values
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+fn derive_key(passphrase: &str, salt: &[u8], work_factor: u32) -> Result<[u8; 32], Error> {
+    // checked before any work is done, as the header is only authenticated
+    // with the key derived from it
+    if work_factor == 0 || work_factor > u32::from(MAX_WORK_FACTOR) {
+        return Err(Error::Decode(format!("unsupported scrypt work factor {}", work_factor)));
+    }
+    let params = scrypt::Params::new(work_factor as u8, SCRYPT_R, SCRYPT_P, 32)
+        .map_err(|_| Error::Decode(format!("invalid scrypt work factor {}", work_factor)))?;
+    let mut key = [0; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| Error::Decode("invalid scrypt output length".to_string()))?;
+    Ok(key)
+}
+
+// a payment spending the oldest outputs of `from` that cover it
+fn spend_outputs(blockchain: &Blockchain, from: &str, to: &str, amount: u64, fee: u64) -> Result<Transaction, Error> {
+    let unspent = blockchain.unspent_outputs(from);
+    let available = unspent.iter().fold(0, |total: u64, (_, output)| total.saturating_add(output.amount));
+    let needed = amount.checked_add(fee).ok_or(Error::InsufficientFunds { needed: u64::MAX, available })?;
+
+    let mut inputs = Vec::new();
+    let mut gathered: u64 = 0;
+    for (outpoint, output) in unspent {
+        if gathered >= needed {
+            break;
+        }
+        // an output that would overflow the total cannot be spent with the others
+        let total = match gathered.checked_add(output.amount) {
+            Some(total) => total,
+            None => continue,
+        };
+        inputs.push(outpoint);
+        gathered = total;
+    }
+    if gathered < needed {
+        return Err(Error::InsufficientFunds { needed, available });
+    }
+
+    let mut outputs = vec![Output { recipient: to.to_string(), amount }];
+    if gathered > needed {
+        outputs.push(Output { recipient: from.to_string(), amount: gathered - needed });
+    }
+
+    Ok(Transaction { inputs, outputs, fee, ..Transaction::default() })
+}
+
+// a payment out of the balance of `from`
+fn debit_account(blockchain: &Blockchain, from: &str, to: &str, amount: u64, fee: u64) -> Result<Transaction, Error> {
+    let available = blockchain.balance(from);
+    let needed = amount.saturating_add(fee);
+    if needed > available {
+        return Err(Error::InsufficientFunds { needed, available });
+    }
+
+    Ok(Transaction {
+        sender: Some(from.to_string()),
+        outputs: vec![Output { recipient: to.to_string(), amount }],
+        fee,
+        nonce: blockchain.next_nonce(from),
+        ..Transaction::default()
+    })
+}
+
+/// Keypairs held by an operator, kept in an encrypted keystore file.
+///
+/// The keystore starts with an 8 byte magic, a format version, the scrypt
+/// work factor, a random salt and a random nonce. The keypairs follow,
+/// encrypted with XChaCha20-Poly1305 under a key derived from the
+/// passphrase with scrypt. The header is authenticated along with the keys,
+/// so tampering with either is caught as a wrong passphrase.
+#[derive(Debug,Clone)]
+pub struct Wallet {
+    keys: Vec<Keypair>,
+    work_factor: u8,
+}
+
+impl Default for Wallet {
+    fn default() -> Wallet {
+        Wallet {
+            keys: Vec::new(),
+            work_factor: DEFAULT_WORK_FACTOR,
+        }
+    }
+}
+
+impl Wallet {
+    /// Creates a wallet without any keys.
+    pub fn new() -> Wallet {
+        Wallet::default()
+    }
+
+    /// Decrypts the keystore at `path` with `passphrase`.
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Wallet, Error> {
+        let contents = fs::read(path)?;
+        if contents.len() < MAGIC.len() || &contents[..MAGIC.len()] != MAGIC {
+            return Err(Error::Decode("not a keystore file".to_string()));
+        }
+
+        let mut decoder = Decoder::new(&contents[MAGIC.len()..]);
+        let version = decoder.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Decode(format!("unsupported keystore version {}", version)));
+        }
+        let work_factor = decoder.u32()?;
+        let salt = decoder.bytes()?;
+        let nonce = decoder.bytes()?;
+        if nonce.len() != NONCE_LEN {
+            return Err(Error::Decode("invalid keystore nonce".to_string()));
+        }
+        let header = &contents[..contents.len() - decoder.remaining()];
+        let ciphertext = decoder.bytes()?;
+        decoder.finish()?;
+
+        let key = derive_key(passphrase, salt, work_factor)?;
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| Error::InvalidPassphrase)?;
+
+        let mut decoder = Decoder::new(&plaintext);
+        let mut keys = Vec::new();
+        for _ in 0..decoder.u64()? {
+            keys.push(Keypair::from_bytes(decoder.bytes()?)?);
+        }
+        decoder.finish()?;
+
+        Ok(Wallet { keys, work_factor: work_factor as u8 })
+    }
+
+    /// Encrypts the wallet with `passphrase` and writes it to `path`,
+    /// replacing the file only once the new one is complete.
+    pub fn save<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<(), Error> {
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut header = MAGIC.to_vec();
+        header.extend(Encoder::new().u32(FORMAT_VERSION).u32(u32::from(self.work_factor)).bytes(&salt).bytes(&nonce).finish());
+
+        let mut plaintext = Encoder::new();
+        plaintext.u64(self.keys.len() as u64);
+        for keypair in &self.keys {
+            plaintext.bytes(&keypair.to_bytes());
+        }
+
+        let key = derive_key(passphrase, &salt, u32::from(self.work_factor))?;
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &plaintext.finish(), aad: &header })
+            .expect("encryption does not fail for in-memory data");
+
+        let mut contents = header;
+        contents.extend(Encoder::new().bytes(&ciphertext).finish());
+
+        let path = path.as_ref();
+        let temp = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&temp)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// Sets the scrypt work factor used by `save`, which fails unless it is
+    /// between 1 and `MAX_WORK_FACTOR`.
+    pub fn set_work_factor(&mut self, work_factor: u8) {
+        self.work_factor = work_factor;
+    }
+
+    /// Generates a new keypair and returns its address.
+    pub fn generate(&mut self, scheme: Scheme) -> String {
+        let keypair = Keypair::generate(scheme);
+        let address = keypair.public_key().address();
+        self.keys.push(keypair);
+        address
+    }
+
+    /// Adds an existing keypair and returns its address.
+    pub fn import(&mut self, keypair: Keypair) -> String {
+        let address = keypair.public_key().address();
+        self.keys.push(keypair);
+        address
+    }
+
+    /// Addresses of every key in the wallet, in the order they were added.
+    pub fn addresses(&self) -> Vec<String> {
+        self.keys.iter().map(|keypair| keypair.public_key().address()).collect()
+    }
+
+    /// The keypair that owns `address`.
+    pub fn keypair(&self, address: &str) -> Option<&Keypair> {
+        self.keys.iter().find(|keypair| keypair.public_key().address() == address)
+    }
+
+    /// Coins held across every address in the wallet.
+    pub fn balance(&self, blockchain: &Blockchain) -> u64 {
+        self.addresses().iter().fold(0, |total, address| total.saturating_add(blockchain.balance(address)))
+    }
+
+    /// Builds and signs a transaction paying `amount` to `to` from `from`,
+    /// leaving `fee` for the miner.
+    ///
+    /// On unspent output chains the payment spends the outputs of `from`
+    /// and returns any change to it. On account chains it is paid out of
+    /// the balance of `from` with its next nonce.
+    pub fn transfer(&self, blockchain: &Blockchain, from: &str, to: &str, amount: u64, fee: u64) -> Result<Transaction, Error> {
+        keys::check_address(to)?;
+        let keypair = self.keypair(from).ok_or_else(|| Error::UnknownAddress(from.to_string()))?;
+
+        let mut tx = match blockchain.params().state_model {
+            StateModel::Utxo => spend_outputs(blockchain, from, to, amount, fee)?,
+            StateModel::Account => debit_account(blockchain, from, to, amount, fee)?,
+        };
+        tx.sign(keypair);
+        Ok(tx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genesis::{Allocation, GenesisSpec};
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn keystore_needs_the_passphrase() {
+        let path = env::temp_dir().join(format!("blockchain-keystore-{}", process::id()));
+        let mut wallet = Wallet::new();
+        wallet.set_work_factor(4);
+        let ed25519 = wallet.generate(Scheme::Ed25519);
+        let secp256k1 = wallet.generate(Scheme::Secp256k1);
+        wallet.save(&path, "correct horse").unwrap();
+
+        let loaded = Wallet::load(&path, "correct horse").unwrap();
+        assert_eq!(loaded.addresses(), vec![ed25519, secp256k1]);
+        assert!(matches!(Wallet::load(&path, "wrong horse"), Err(Error::InvalidPassphrase)));
+
+        // the work factor is authenticated along with the keys
+        let mut contents = fs::read(&path).unwrap();
+        contents[MAGIC.len() + 7] ^= 1;
+        fs::write(&path, &contents).unwrap();
+        assert!(Wallet::load(&path, "correct horse").is_err());
+
+        // and one out of range is refused before running scrypt, rather than
+        // cut down to a byte
+        contents[MAGIC.len() + 4..MAGIC.len() + 8].copy_from_slice(&(256u32 + 4).to_be_bytes());
+        fs::write(&path, &contents).unwrap();
+        assert!(matches!(Wallet::load(&path, "correct horse"), Err(Error::Decode(_))));
+        wallet.set_work_factor(MAX_WORK_FACTOR + 1);
+        assert!(matches!(wallet.save(&path, "correct horse"), Err(Error::Decode(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn transfers_spend_outputs_and_return_change() {
+        let mut wallet = Wallet::new();
+        let alice = wallet.generate(Scheme::Ed25519);
+        let bob = Keypair::generate(Scheme::Secp256k1).public_key().address();
+
+        let spec = GenesisSpec {
+            allocations: vec![
+                Allocation { address: alice.clone(), amount: 30 },
+                Allocation { address: alice.clone(), amount: 20 },
+            ],
+            ..GenesisSpec::default()
+        };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        assert_eq!(wallet.balance(&blockchain), 50);
+
+        let tx = wallet.transfer(&blockchain, &alice, &bob, 35, 1).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        blockchain.add_transactions(vec![tx]).unwrap();
+        assert_eq!(blockchain.balance(&bob), 35);
+        assert_eq!(blockchain.balance(&alice), 14);
+
+        assert!(matches!(wallet.transfer(&blockchain, &alice, &bob, 14, 1), Err(Error::InsufficientFunds { needed: 15, available: 14 })));
+        assert!(matches!(wallet.transfer(&blockchain, &alice, "bob", 1, 0), Err(Error::InvalidAddress(_))));
+        assert!(matches!(wallet.transfer(&blockchain, &bob, &alice, 1, 0), Err(Error::UnknownAddress(_))));
+    }
+
+    #[test]
+    fn transfers_on_account_chains_use_the_next_nonce() {
+        let mut wallet = Wallet::new();
+        let alice = wallet.generate(Scheme::Secp256k1);
+        let bob = Keypair::generate(Scheme::Ed25519).public_key().address();
+
+        let spec = GenesisSpec {
+            state_model: StateModel::Account,
+            allocations: vec![Allocation { address: alice.clone(), amount: 50 }],
+            ..GenesisSpec::default()
+        };
+        let mut blockchain = Blockchain::from_spec(&spec).unwrap();
+        for nonce in 0..2 {
+            let tx = wallet.transfer(&blockchain, &alice, &bob, 20, 1).unwrap();
+            assert_eq!(tx.nonce, nonce);
+            blockchain.add_transactions(vec![tx]).unwrap();
+        }
+        assert_eq!(blockchain.balance(&bob), 40);
+        assert_eq!(blockchain.balance(&alice), 8);
+        assert!(matches!(wallet.transfer(&blockchain, &alice, &bob, 8, 1), Err(Error::InsufficientFunds { needed: 9, available: 8 })));
+    }
+}